@@ -1,47 +1,130 @@
+use std::collections::VecDeque;
+
 use aoc_lib::{aoc, color_eyre::eyre::Result, to_lines};
 
 static INPUT: &str = include_str!("../../inputs/day01");
 
-fn map_digit(s: &str, dict: &[&str]) -> Option<i64> {
-	dict.iter()
-		.position(|d| s.starts_with(d))
-		.map(|pos| pos as i64 + 1)
+type Dictionary<'a> = &'a [(&'a str, i64)];
+
+const DIGITS: Dictionary = &[
+	("1", 1),
+	("2", 2),
+	("3", 3),
+	("4", 4),
+	("5", 5),
+	("6", 6),
+	("7", 7),
+	("8", 8),
+	("9", 9),
+];
+
+const DIGIT_WORDS: Dictionary = &[
+	("one", 1),
+	("two", 2),
+	("three", 3),
+	("four", 4),
+	("five", 5),
+	("six", 6),
+	("seven", 7),
+	("eight", 8),
+	("nine", 9),
+];
+
+/// Aho-Corasick automaton finding every occurrence of every token in a single pass,
+/// including overlapping ones, like `eightwo`.
+struct Matcher {
+	/// Transitions of every state, with the failure links already folded in.
+	next: Vec<[usize; 256]>,
+	/// Lengths and values of the tokens ending in every state.
+	out: Vec<Vec<(usize, i64)>>,
 }
 
-fn to_digits(line: &str, include_words: bool) -> Vec<i64> {
-	const DIGITS: &[&str] = &["1", "2", "3", "4", "5", "6", "7", "8", "9"];
-	const DIGIT_WORDS: &[&str] = &[
-		"one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
-	];
-
-	(0..line.len())
-		.filter_map(|idx| {
-			map_digit(&line[idx..], DIGITS).or(include_words
-				.then(|| map_digit(&line[idx..], DIGIT_WORDS))
-				.flatten())
-		})
-		.collect()
+impl Matcher {
+	const ROOT: usize = 0;
+
+	fn new<'a>(dicts: &[Dictionary<'a>]) -> Self {
+		let mut next = vec![[Self::ROOT; 256]];
+		let mut out = vec![Vec::new()];
+
+		for &(token, value) in dicts.iter().copied().flatten() {
+			let mut state = Self::ROOT;
+			for b in token.bytes() {
+				if next[state][b as usize] == Self::ROOT {
+					next.push([Self::ROOT; 256]);
+					out.push(Vec::new());
+					next[state][b as usize] = next.len() - 1;
+				}
+				state = next[state][b as usize];
+			}
+			out[state].push((token.len(), value));
+		}
+
+		// Breadth first, so the failure link of every state is done before its children.
+		// At the time a state is visited, its transitions only lead to its children in the trie.
+		let mut fail = vec![Self::ROOT; next.len()];
+		let mut queue: VecDeque<_> = next[Self::ROOT]
+			.iter()
+			.copied()
+			.filter(|&child| child != Self::ROOT)
+			.collect();
+		while let Some(state) = queue.pop_front() {
+			let row = next[state];
+			for (b, &child) in row.iter().enumerate() {
+				let fallback = next[fail[state]][b];
+				if child == Self::ROOT {
+					next[state][b] = fallback;
+				} else {
+					fail[child] = fallback;
+					let inherited = out[fallback].clone();
+					out[child].extend(inherited);
+					queue.push_back(child);
+				}
+			}
+		}
+
+		Matcher { next, out }
+	}
+
+	/// Start positions and values of all the matches, ordered by their end.
+	fn find_iter<'a>(&'a self, s: &'a str) -> impl Iterator<Item = (usize, i64)> + 'a {
+		s.bytes()
+			.enumerate()
+			.scan(Self::ROOT, |state, (i, b)| {
+				*state = self.next[*state][b as usize];
+				Some((i, *state))
+			})
+			.flat_map(|(i, state)| {
+				self.out[state]
+					.iter()
+					.map(move |&(len, value)| (i + 1 - len, value))
+			})
+	}
 }
 
-fn common(input: &str, include_words: bool) -> i64 {
+fn common(input: &str, dicts: &[Dictionary]) -> i64 {
+	let matcher = Matcher::new(dicts);
 	let lines = to_lines(input);
 
 	lines
 		.map(|line| {
-			let digits = to_digits(line, include_words);
-			let fst = digits.first().expect("line should have at least one digit");
-			let lst = digits.last().expect("line should have at least one digit");
+			let matches = || matcher.find_iter(line);
+			let (_, fst) = matches()
+				.min_by_key(|&(start, _)| start)
+				.expect("line should have at least one digit");
+			let (_, lst) = matches()
+				.max_by_key(|&(start, _)| start)
+				.expect("line should have at least one digit");
 			fst * 10 + lst
 		})
 		.sum()
 }
 
 fn part1(input: &str) -> Result<i64> {
-	Ok(common(input, false))
+	Ok(common(input, &[DIGITS]))
 }
 
 fn part2(input: &str) -> Result<i64> {
-	Ok(common(input, true))
+	Ok(common(input, &[DIGITS, DIGIT_WORDS]))
 }
 
 #[allow(dead_code)]
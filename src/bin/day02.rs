@@ -1,57 +1,115 @@
-use aoc_lib::{aoc, color_eyre::eyre::Result, map_with_idx, to_lines};
+use std::collections::BTreeMap;
+
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, eyre, Result},
+	to_lines,
+};
 
 static INPUT: &str = include_str!("../../inputs/day02");
 
-fn str_to_game(s: &str) -> [i64; 3] {
-	s.split(", ").fold([0, 0, 0], |[r, g, b], curr| {
-		let (v, c) = curr.split_once(' ').unwrap();
-		if c == "red" {
-			[r + v.parse::<i64>().unwrap(), g, b]
-		} else if c == "green" {
-			[r, g + v.parse::<i64>().unwrap(), b]
-		} else if c == "blue" {
-			[r, g, b + v.parse::<i64>().unwrap()]
-		} else {
-			panic!()
-		}
-	})
-}
+/// Count of cubes of every color, either drawn at once or available in the bag.
+type Bag<'a> = BTreeMap<&'a str, i64>;
 
-fn parse_line(line: &str) -> [i64; 3] {
-	let (_, games_s) = line.split_once(": ").unwrap();
-	let games = games_s.split("; ").map(str_to_game);
-	games
-		.reduce(|prev, curr| map_with_idx(prev, |i, e| e.max(curr[i])))
-		.unwrap()
+const ELF_BAG: [(&str, i64); 3] = [("red", 12), ("green", 13), ("blue", 14)];
+
+struct Game<'a> {
+	id: i64,
+	draws: Vec<Bag<'a>>,
 }
 
-fn part1(input: &str) -> Result<i64> {
-	let allowed_cubes = [12, 13, 14];
-	let ans = to_lines(input)
-		.enumerate()
-		.map(|(i, line)| {
-			let id = i + 1;
-			let cubes_used = parse_line(line);
-			(id as i64, cubes_used)
+impl<'a> Game<'a> {
+	fn parse(line: &'a str) -> Result<Self> {
+		let (id, draws_s) = line
+			.split_once(": ")
+			.ok_or_else(|| eyre!("missing game id in {line:?}"))?;
+		let Some(id) = id.strip_prefix("Game ") else {
+			bail!("invalid game id: {id:?}");
+		};
+		let id = id.parse()?;
+		let draws = draws_s
+			.split("; ")
+			.map(|draw| {
+				draw.split(", ").try_fold(Bag::new(), |mut bag, cubes| {
+					let Some((v, color)) = cubes.split_once(' ') else {
+						bail!("invalid cubes: {cubes:?}");
+					};
+					*bag.entry(color).or_default() += v.parse::<i64>()?;
+					Ok(bag)
+				})
+			})
+			.collect::<Result<_>>()?;
+		Ok(Game { id, draws })
+	}
+
+	/// The smallest bag every draw of the game could have come from.
+	fn minimal_bag(&self) -> Bag<'a> {
+		self.draws.iter().fold(Bag::new(), |mut bag, draw| {
+			for (&color, &v) in draw {
+				let max = bag.entry(color).or_default();
+				*max = (*max).max(v);
+			}
+			bag
 		})
-		.filter(|(_, cubes_used)| {
-			cubes_used
-				.iter()
-				.copied()
-				.zip(allowed_cubes)
-				.all(|(used, allowed)| used <= allowed)
+	}
+
+	/// Draws, along with their indices, which take more cubes of some color than `bag` has.
+	/// Colors missing from the bag are treated as having no cubes at all.
+	fn violations<'b>(&'b self, bag: &'b Bag) -> impl Iterator<Item = (usize, &'b Bag<'a>)> + 'b {
+		self.draws.iter().enumerate().filter(|(_, draw)| {
+			draw.iter()
+				.any(|(color, &v)| v > bag.get(color).copied().unwrap_or(0))
 		})
-		.map(|(id, _)| id)
-		.sum();
+	}
+
+	fn is_possible(&self, bag: &Bag) -> bool {
+		self.violations(bag).next().is_none()
+	}
+}
+
+fn format_bag(bag: &Bag) -> String {
+	bag.iter()
+		.map(|(color, v)| format!("{v} {color}"))
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+fn possible_games(input: &str, bag: &Bag) -> Result<i64> {
+	let report = std::env::var_os("DAY02_REPORT").is_some();
+	let mut ans = 0;
+	for line in to_lines(input) {
+		let game = Game::parse(line)?;
+		if report {
+			println!(
+				"Game {}: minimal bag {}",
+				game.id,
+				format_bag(&game.minimal_bag())
+			);
+			for (i, draw) in game.violations(bag) {
+				println!("  draw {} violates the bag: {}", i + 1, format_bag(draw));
+			}
+		}
+		if game.is_possible(bag) {
+			ans += game.id;
+		}
+	}
 	Ok(ans)
 }
 
+fn part1(input: &str) -> Result<i64> {
+	possible_games(input, &Bag::from(ELF_BAG))
+}
+
 fn part2(input: &str) -> Result<i64> {
-	let ans = to_lines(input)
-		.map(parse_line)
-		.map(|game| game.into_iter().product::<i64>())
-		.sum();
-	Ok(ans)
+	to_lines(input)
+		.map(|line| {
+			let bag = Game::parse(line)?.minimal_bag();
+			Ok(ELF_BAG
+				.iter()
+				.map(|(color, _)| bag.get(color).copied().unwrap_or(0))
+				.product::<i64>())
+		})
+		.sum()
 }
 
 #[allow(dead_code)]
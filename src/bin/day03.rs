@@ -1,70 +1,139 @@
-use std::{collections::HashSet, ops::Range};
+use std::collections::{BTreeSet, HashMap};
 
-use aoc_lib::{aoc, color_eyre::eyre::Result, grid::Grid, rangemap::RangeMap, regex::Regex};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{eyre, Result},
+	grid::{Grid, Point},
+};
 
 static INPUT: &str = include_str!("../../inputs/day03");
 
-fn part1(input: &str) -> Result<i64> {
-	let grid = Grid::for_str(input).unwrap();
+struct Number {
+	value: i64,
+	/// First and last position of the digits, which are always in a single row.
+	span: (Point, Point),
+}
+
+struct Symbol {
+	c: char,
+	pos: Point,
+}
 
-	let re = Regex::new(r"\d+").unwrap();
-	let check_symbol = |b: u8| b != b'.' && !b.is_ascii_digit();
+struct Schematic {
+	numbers: Vec<Number>,
+	symbols: Vec<Symbol>,
+	/// Indices of the symbols adjacent to every number.
+	number_symbols: Vec<BTreeSet<usize>>,
+	/// Indices of the numbers adjacent to every symbol.
+	symbol_numbers: Vec<BTreeSet<usize>>,
+}
 
-	let res = re
-		.find_iter(input)
-		.filter(|m| {
-			let Range { start, end } = m.range();
-			let start_pos = grid.idx_to_pos(start).unwrap();
-			let end_pos = grid.idx_to_pos(end - 1).unwrap();
+impl Schematic {
+	fn parse(input: &str) -> Result<Self> {
+		let grid = Grid::for_str(input).ok_or_else(|| eyre!("invalid schematic grid"))?;
+
+		let mut numbers = Vec::new();
+		let mut symbols = Vec::new();
+		for (y, row) in grid.iter_rows().enumerate() {
+			let y = y as i64;
+			let mut x = 0;
+			while x < row.len() {
+				let b = row[x];
+				if b.is_ascii_digit() {
+					let len = row[x..].iter().take_while(|b| b.is_ascii_digit()).count();
+					let value = std::str::from_utf8(&row[x..x + len])?.parse()?;
+					let start = (x as i64, y);
+					let end = ((x + len - 1) as i64, y);
+					numbers.push(Number {
+						value,
+						span: (start, end),
+					});
+					x += len;
+					continue;
+				}
+				if b != b'.' {
+					symbols.push(Symbol {
+						c: b as char,
+						pos: (x as i64, y),
+					});
+				}
+				x += 1;
+			}
+		}
 
-			grid.adjacent_area(start_pos, end_pos)
-				.any(|pos| check_symbol(grid[pos]))
+		let symbol_at: HashMap<_, _> = symbols
+			.iter()
+			.enumerate()
+			.map(|(i, symbol)| (symbol.pos, i))
+			.collect();
+		let mut number_symbols = vec![BTreeSet::new(); numbers.len()];
+		let mut symbol_numbers = vec![BTreeSet::new(); symbols.len()];
+		for (i, number) in numbers.iter().enumerate() {
+			let (start, end) = number.span;
+			for pos in grid.adjacent_area(start, end) {
+				if let Some(&s) = symbol_at.get(&pos) {
+					number_symbols[i].insert(s);
+					symbol_numbers[s].insert(i);
+				}
+			}
+		}
+
+		Ok(Schematic {
+			numbers,
+			symbols,
+			number_symbols,
+			symbol_numbers,
 		})
-		.map(|m| m.as_str().parse::<i64>().unwrap())
-		.sum();
+	}
 
-	Ok(res)
-}
+	/// Numbers adjacent to at least one symbol matching `pred`, each reported once.
+	fn numbers_touching(&self, pred: impl Fn(char) -> bool) -> impl Iterator<Item = &Number> {
+		self.numbers
+			.iter()
+			.zip(&self.number_symbols)
+			.filter(move |(_, symbols)| symbols.iter().any(|&s| pred(self.symbols[s].c)))
+			.map(|(number, _)| number)
+	}
 
-fn part2(input: &str) -> Result<i64> {
-	let grid = Grid::for_str(input).unwrap();
-
-	let re = Regex::new(r"\d+").unwrap();
-	let mut num_map: RangeMap<_, i64> = re
-		.find_iter(input)
-		.map(|m| (m.range(), m.as_str().parse().unwrap()))
-		.collect();
-
-	let gears = input
-		.as_bytes()
-		.iter()
-		.enumerate()
-		.filter(|(_, &b)| b == b'*');
-
-	let mut res = 0;
-	for (gear, _) in gears {
-		let pos_to_check = grid.adjacent_pos(grid.idx_to_pos(gear).unwrap());
-
-		let part_nums: HashSet<_> = pos_to_check
-			.map(|pos| grid.pos_to_idx(pos).unwrap())
-			.filter_map(|idx| num_map.get_key_value(&idx))
-			.map(|(r, &v)| (r.clone(), v))
-			.collect();
+	/// Numbers without any adjacent symbol.
+	fn lone_numbers(&self) -> impl Iterator<Item = &Number> {
+		self.numbers
+			.iter()
+			.zip(&self.number_symbols)
+			.filter(|(_, symbols)| symbols.is_empty())
+			.map(|(number, _)| number)
+	}
 
-		if part_nums.len() != 2 {
-			continue;
-		}
+	/// Symbols `c` adjacent to exactly `n` numbers, along with those numbers.
+	fn symbols_with_neighbors(
+		&self,
+		c: char,
+		n: usize,
+	) -> impl Iterator<Item = (&Symbol, Vec<&Number>)> {
+		self.symbols
+			.iter()
+			.zip(&self.symbol_numbers)
+			.filter(move |(symbol, numbers)| symbol.c == c && numbers.len() == n)
+			.map(|(symbol, numbers)| (symbol, numbers.iter().map(|&i| &self.numbers[i]).collect()))
+	}
+}
 
-		let mut ratio = 1;
-		for (r, v) in part_nums {
-			num_map.remove(r);
-			ratio *= v;
+fn part1(input: &str) -> Result<i64> {
+	let schematic = Schematic::parse(input)?;
+	if std::env::var_os("DAY03_REPORT").is_some() {
+		for number in schematic.lone_numbers() {
+			let ((x, y), _) = number.span;
+			println!("{} at {x},{y} touches no symbol", number.value);
 		}
-
-		res += ratio;
 	}
+	Ok(schematic.numbers_touching(|_| true).map(|n| n.value).sum())
+}
 
-	Ok(res)
+fn part2(input: &str) -> Result<i64> {
+	Ok(Schematic::parse(input)?
+		.symbols_with_neighbors('*', 2)
+		.map(|(_, numbers)| numbers.iter().map(|n| n.value).product::<i64>())
+		.sum())
 }
 
 #[allow(dead_code)]
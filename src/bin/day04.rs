@@ -1,6 +1,10 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::HashSet;
 
-use aoc_lib::{aoc, color_eyre::eyre::Result, to_lines};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{eyre, Result},
+	to_lines,
+};
 
 static INPUT: &str = include_str!("../../inputs/day04");
 
@@ -33,23 +37,60 @@ fn part1(input: &str) -> Result<i64> {
 	Ok(res)
 }
 
-fn part2(input: &str) -> Result<i64> {
-	let res = to_lines(input)
-		.scan(VecDeque::new(), |pile, line| {
-			let wins = parse_game(line);
-			let copies = pile.pop_front().unwrap_or(0) + 1;
-			for i in 0..wins as usize {
-				if let Some(e) = pile.get_mut(i) {
-					*e += copies;
-				} else {
-					pile.push_back(copies);
-				}
+/// How the won scratchcards cascade through the pile.
+struct Cascade {
+	/// Number of instances of every card, including the original.
+	copies: Vec<u128>,
+	/// Earlier cards which won copies of every card, along with how many copies each contributed.
+	sources: Vec<Vec<(usize, u128)>>,
+}
+
+impl Cascade {
+	fn new(wins: &[usize]) -> Result<Self> {
+		let mut copies = vec![1u128; wins.len()];
+		let mut sources = vec![Vec::new(); wins.len()];
+		for (card, &won) in wins.iter().enumerate() {
+			let count = copies[card];
+			for next in (card + 1..wins.len()).take(won) {
+				copies[next] = copies[next]
+					.checked_add(count)
+					.ok_or_else(|| eyre!("copies of card {} overflow", next + 1))?;
+				sources[next].push((card, count));
 			}
-			Some(copies)
+		}
+		Ok(Cascade { copies, sources })
+	}
+
+	fn total(&self) -> Result<u128> {
+		self.copies.iter().try_fold(0u128, |acc, &c| {
+			acc.checked_add(c)
+				.ok_or_else(|| eyre!("total number of cards overflows"))
 		})
-		.sum();
+	}
+}
 
-	Ok(res)
+fn part2(input: &str) -> Result<u128> {
+	let wins: Vec<_> = to_lines(input)
+		.map(|line| parse_game(line) as usize)
+		.collect();
+	let cascade = Cascade::new(&wins)?;
+
+	if std::env::var_os("DAY04_CASCADE").is_some() {
+		for (card, (copies, sources)) in cascade.copies.iter().zip(&cascade.sources).enumerate() {
+			let sources = sources
+				.iter()
+				.map(|(src, count)| format!("{count} from card {}", src + 1))
+				.collect::<Vec<_>>();
+			let sources = if sources.is_empty() {
+				String::new()
+			} else {
+				format!(" + {}", sources.join(" + "))
+			};
+			println!("card {}: {copies} = 1 original{sources}", card + 1);
+		}
+	}
+
+	cascade.total()
 }
 
 #[allow(dead_code)]
@@ -1,24 +1,66 @@
-use aoc_lib::{aoc, color_eyre::eyre::Result, to_lines};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{eyre, Result},
+	to_lines,
+};
 
 static INPUT: &str = include_str!("../../inputs/day06");
 
-fn find_res(time: i64, dist: i64) -> i64 {
-	(1..time)
-		.filter(|t| {
-			let rest = time - t;
-			t * rest > dist
-		})
-		.count() as i64
+/// Largest `x` such that `x * x <= n`.
+fn isqrt(n: u128) -> u128 {
+	if n < 2 {
+		return n;
+	}
+	// start above the root, from there on Newton's method only goes down
+	let mut x = 1 << (128 - n.leading_zeros()).div_ceil(2);
+	loop {
+		let next = (x + n / x) / 2;
+		if next >= x {
+			return x;
+		}
+		x = next;
+	}
 }
 
-fn parse(input: &str) -> impl Iterator<Item = i64> + '_ {
+/// Count hold times `t` for which `t * (time - t) > dist`.
+/// The winning hold times lie strictly between the roots of `t^2 - time * t + dist`,
+/// symmetrically around `time / 2`, so it's enough to find the first one.
+/// Fails for races of 2^64 milliseconds or more, whose discriminant doesn't fit in a `u128`.
+fn find_res(time: u128, dist: u128) -> Result<u128> {
+	let wins = |t: u128| t * (time - t) > dist;
+
+	let square = time
+		.checked_mul(time)
+		.ok_or_else(|| eyre!("race too long: {time}"))?;
+	let Some(disc) = dist.checked_mul(4).and_then(|d| square.checked_sub(d)) else {
+		return Ok(0);
+	};
+
+	// the integer square root is off by less than one,
+	// so this is at most a step away from the first winning hold time
+	let mut first = (time - isqrt(disc)) / 2;
+	while first > 0 && wins(first - 1) {
+		first -= 1;
+	}
+	while first <= time / 2 && !wins(first) {
+		first += 1;
+	}
+
+	if first > time / 2 {
+		Ok(0)
+	} else {
+		Ok(time - 2 * first + 1)
+	}
+}
+
+fn parse(input: &str) -> impl Iterator<Item = u128> + '_ {
 	let (_, nums) = input.split_once(':').unwrap();
 	nums.trim()
 		.split_ascii_whitespace()
 		.map(|n| n.parse().unwrap())
 }
 
-fn part1(input: &str) -> Result<i64> {
+fn part1(input: &str) -> Result<u128> {
 	let mut input = to_lines(input);
 	let time = input.next().map(parse).unwrap();
 	let distance = input.next().map(parse).unwrap();
@@ -26,26 +68,30 @@ fn part1(input: &str) -> Result<i64> {
 	let res = time
 		.zip(distance)
 		.map(|(time, distance)| find_res(time, distance))
-		.product();
+		.product::<Result<_>>()?;
 
 	Ok(res)
 }
 
-fn parse2(input: &str) -> i64 {
-	let (_, nums) = input.split_once(':').unwrap();
-	nums.trim()
+fn parse2(input: Option<&str>) -> Result<u128> {
+	let line = input.ok_or_else(|| eyre!("missing line"))?;
+	let (_, nums) = line
+		.split_once(':')
+		.ok_or_else(|| eyre!("invalid line: {line:?}"))?;
+	let num = nums
+		.trim()
 		.split_ascii_whitespace()
 		.collect::<String>()
-		.parse()
-		.unwrap()
+		.parse()?;
+	Ok(num)
 }
 
-fn part2(input: &str) -> Result<i64> {
+fn part2(input: &str) -> Result<u128> {
 	let mut input = to_lines(input);
-	let time = input.next().map(parse2).unwrap();
-	let distance = input.next().map(parse2).unwrap();
+	let time = parse2(input.next())?;
+	let distance = parse2(input.next())?;
 
-	Ok(find_res(time, distance))
+	find_res(time, distance)
 }
 
 #[allow(dead_code)]
@@ -59,3 +105,22 @@ aoc! {
 	part1 => (EX_INPUT) 288,
 	part2 => (EX_INPUT) 71503
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn huge_races() {
+		let time = u64::MAX as u128;
+		assert_eq!(find_res(time, 0).unwrap(), time - 1);
+		assert!(find_res(time + 1, 0).is_err());
+		assert!(part2("Time: 18446744073709551616\nDistance: 1").is_err());
+	}
+
+	#[test]
+	fn malformed_input() {
+		assert!(part2("Time: 7").is_err());
+		assert!(part2("Time: 7\nDistance: x").is_err());
+	}
+}
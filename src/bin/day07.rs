@@ -1,85 +1,208 @@
-use aoc_lib::{aoc, color_eyre::eyre::Result, to_lines};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, eyre, Result},
+	to_lines,
+};
 
 static INPUT: &str = include_str!("../../inputs/day07");
 
-fn parse_hand<const N: usize>(
-	order: [u8; N],
-	cards: impl IntoIterator<Item = u8>,
-) -> (Vec<usize>, [u8; N]) {
-	let mut cnt = [0; N];
-	let cards: Vec<_> = cards
-		.into_iter()
-		.map(|card| order.iter().position(|&r| r == card).unwrap())
-		.collect();
-	cards.iter().for_each(|&card| cnt[card] += 1);
-	(cards, cnt)
+struct HandType {
+	name: &'static str,
+	/// Sizes of the groups of equal cards, largest first.
+	groups: &'static [usize],
 }
 
-fn part1(input: &str) -> Result<i64> {
-	const CARD_ORDER: [u8; 13] = [
-		b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'T', b'J', b'Q', b'K', b'A',
-	];
-
-	let mut hands: Vec<_> = to_lines(input)
-		.map(|line| {
-			let (cards, bid) = line.split_once(' ').unwrap();
-			let bid: i64 = bid.parse().unwrap();
-			let (cards, cnt) = parse_hand(CARD_ORDER, cards.as_bytes().iter().copied());
-			let mut cnt: Vec<_> = cnt.into_iter().filter(|&c| c != 0).collect();
-			cnt.sort_unstable_by(|a, b| b.cmp(a));
-			(cnt, cards, bid)
-		})
-		.collect();
+/// From the weakest to the strongest.
+const HAND_TYPES: &[HandType] = &[
+	HandType {
+		name: "high card",
+		groups: &[1, 1, 1, 1, 1],
+	},
+	HandType {
+		name: "one pair",
+		groups: &[2, 1, 1, 1],
+	},
+	HandType {
+		name: "two pair",
+		groups: &[2, 2, 1],
+	},
+	HandType {
+		name: "three of a kind",
+		groups: &[3, 1, 1],
+	},
+	HandType {
+		name: "full house",
+		groups: &[3, 2],
+	},
+	HandType {
+		name: "four of a kind",
+		groups: &[4, 1],
+	},
+	HandType {
+		name: "five of a kind",
+		groups: &[5],
+	},
+];
 
-	hands.sort_unstable_by(|(ha, ca, _), (hb, cb, _)| match ha.cmp(hb) {
-		std::cmp::Ordering::Equal => ca.cmp(cb),
-		r => r,
-	});
-
-	let res = hands
-		.into_iter()
-		.enumerate()
-		.map(|(rank, (_, _, bid))| (rank as i64 + 1) * bid)
-		.sum();
+struct Hand<'a> {
+	cards: &'a str,
+	bid: i64,
+	/// Index into the ranker's hand types.
+	typ: usize,
+	/// Index of every card in the ranker's card order.
+	strengths: Vec<usize>,
+}
 
-	Ok(res)
+struct HandRanker<'a> {
+	/// From the weakest to the strongest.
+	card_order: &'a [u8],
+	/// Cards which pretend to be whatever makes the hand strongest.
+	/// They still need a place in the card order for breaking ties.
+	wildcards: &'a [u8],
+	hand_types: &'a [HandType],
 }
 
-fn part2(input: &str) -> Result<i64> {
-	const CARD_ORDER: [u8; 13] = [
-		b'J', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'T', b'Q', b'K', b'A',
-	];
-
-	let mut hands: Vec<_> = to_lines(input)
-		.map(|line| {
-			let (cards, bid) = line.split_once(' ').unwrap();
-			let bid: i64 = bid.parse().unwrap();
-			let (cards, mut cnt) = parse_hand(CARD_ORDER, cards.as_bytes().iter().copied());
-			let joker_cnt = cnt[0];
-			cnt[0] = 0;
-			let mut cnt: Vec<_> = cnt.into_iter().filter(|&c| c != 0).collect();
-			if cnt.is_empty() {
-				cnt.push(joker_cnt);
+impl HandRanker<'_> {
+	fn validate(&self) -> Result<()> {
+		if let Some(card) = self
+			.wildcards
+			.iter()
+			.find(|card| !self.card_order.contains(card))
+		{
+			bail!("wildcard {} is missing from the card order", *card as char);
+		}
+		if let Some((idx, card)) = self
+			.card_order
+			.iter()
+			.enumerate()
+			.find(|(idx, card)| self.card_order[..*idx].contains(card))
+		{
+			bail!(
+				"card {} is in the card order twice, at {idx}",
+				*card as char
+			);
+		}
+		Ok(())
+	}
+
+	fn classify(&self, cards: &[u8]) -> Result<usize> {
+		let mut cnt = vec![0; self.card_order.len()];
+		let mut wild_cnt = 0;
+		for card in cards {
+			if self.wildcards.contains(card) {
+				wild_cnt += 1;
 			} else {
-				cnt.sort_unstable_by(|a, b| b.cmp(a));
-				cnt[0] += joker_cnt;
+				let Some(idx) = self.card_order.iter().position(|c| c == card) else {
+					bail!("unknown card: {}", *card as char);
+				};
+				cnt[idx] += 1;
 			}
-			(cnt, cards, bid)
+		}
+
+		let mut groups: Vec<_> = cnt.into_iter().filter(|&c| c != 0).collect();
+		groups.sort_unstable_by(|a, b| b.cmp(a));
+		// the wildcards are always best spent making the largest group even larger
+		match groups.first_mut() {
+			Some(largest) => *largest += wild_cnt,
+			None => groups.push(wild_cnt),
+		}
+
+		self.hand_types
+			.iter()
+			.position(|typ| typ.groups == groups)
+			.ok_or_else(|| eyre!("no hand type for {}", String::from_utf8_lossy(cards)))
+	}
+
+	fn parse<'i>(&self, line: &'i str) -> Result<Hand<'i>> {
+		let Some((cards, bid)) = line.split_once(' ') else {
+			bail!("missing bid: {line}");
+		};
+		let typ = self.classify(cards.as_bytes())?;
+		let strengths = cards
+			.bytes()
+			.map(|card| {
+				self.card_order
+					.iter()
+					.position(|&c| c == card)
+					.ok_or_else(|| eyre!("unknown card: {}", card as char))
+			})
+			.collect::<Result<_>>()?;
+		let bid = bid
+			.parse()
+			.map_err(|err| eyre!("invalid bid {bid:?}: {err}"))?;
+
+		Ok(Hand {
+			cards,
+			bid,
+			typ,
+			strengths,
 		})
-		.collect();
+	}
+
+	/// Hands ordered from the weakest to the strongest.
+	fn rank<'i>(&self, input: &'i str) -> Result<Vec<Hand<'i>>> {
+		self.validate()?;
+		let mut hands = to_lines(input)
+			.map(|line| self.parse(line))
+			.collect::<Result<Vec<_>>>()?;
+
+		hands.sort_unstable_by(|a, b| (a.typ, &a.strengths).cmp(&(b.typ, &b.strengths)));
 
-	hands.sort_unstable_by(|(ha, ca, _), (hb, cb, _)| match ha.cmp(hb) {
-		std::cmp::Ordering::Equal => ca.cmp(cb),
-		r => r,
-	});
+		Ok(hands)
+	}
 
-	let res = hands
-		.into_iter()
-		.enumerate()
-		.map(|(rank, (_, _, bid))| (rank as i64 + 1) * bid)
-		.sum();
+	fn table(&self, hands: &[Hand<'_>]) -> String {
+		hands
+			.iter()
+			.enumerate()
+			.map(|(rank, hand)| {
+				let rank = rank as i64 + 1;
+				format!(
+					"{rank:>5} {} {:<15} {:>5} {:>10}\n",
+					hand.cards,
+					self.hand_types[hand.typ].name,
+					hand.bid,
+					rank * hand.bid
+				)
+			})
+			.collect()
+	}
 
-	Ok(res)
+	fn winnings(&self, input: &str) -> Result<i64> {
+		let hands = self.rank(input)?;
+
+		if std::env::var_os("DAY07_TABLE").is_some() {
+			print!("{}", self.table(&hands));
+		}
+
+		let res = hands
+			.iter()
+			.enumerate()
+			.map(|(rank, hand)| (rank as i64 + 1) * hand.bid)
+			.sum();
+
+		Ok(res)
+	}
+}
+
+const PLAIN: HandRanker<'static> = HandRanker {
+	card_order: b"23456789TJQKA",
+	wildcards: b"",
+	hand_types: HAND_TYPES,
+};
+
+const JOKERS: HandRanker<'static> = HandRanker {
+	card_order: b"J23456789TQKA",
+	wildcards: b"J",
+	hand_types: HAND_TYPES,
+};
+
+fn part1(input: &str) -> Result<i64> {
+	PLAIN.winnings(input)
+}
+
+fn part2(input: &str) -> Result<i64> {
+	JOKERS.winnings(input)
 }
 
 #[allow(dead_code)]
@@ -96,3 +219,60 @@ aoc! {
 	part1 => (EX_INPUT) 6440,
 	part2 => (EX_INPUT) 5905
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn classify(ranker: &HandRanker<'_>, cards: &str) -> Result<&'static str> {
+		Ok(HAND_TYPES[ranker.classify(cards.as_bytes())?].name)
+	}
+
+	#[test]
+	fn several_wildcards() {
+		assert_eq!(classify(&JOKERS, "J2J3J").unwrap(), "four of a kind");
+		assert_eq!(classify(&JOKERS, "JJ234").unwrap(), "three of a kind");
+		assert_eq!(classify(&JOKERS, "JJJJJ").unwrap(), "five of a kind");
+		assert_eq!(classify(&PLAIN, "JJJJJ").unwrap(), "five of a kind");
+
+		let ranker = HandRanker {
+			card_order: b"23456789TJQKA",
+			wildcards: b"2J",
+			..PLAIN
+		};
+		assert_eq!(classify(&ranker, "2J2J3").unwrap(), "five of a kind");
+		assert_eq!(classify(&ranker, "2J34K").unwrap(), "three of a kind");
+	}
+
+	#[test]
+	fn missing_hand_type() {
+		let ranker = HandRanker {
+			hand_types: &HAND_TYPES[..HAND_TYPES.len() - 1],
+			..PLAIN
+		};
+		assert!(classify(&ranker, "AAAAK").is_ok());
+		assert!(classify(&ranker, "AAAAA").is_err());
+		assert!(classify(&PLAIN, "AAAAAA").is_err());
+	}
+
+	#[test]
+	fn invalid_setup() {
+		let ranker = HandRanker {
+			card_order: b"23456789TQKA",
+			..JOKERS
+		};
+		assert!(ranker.winnings("J2345 1").is_err());
+		let ranker = HandRanker {
+			card_order: b"J23456789TJQKA",
+			..JOKERS
+		};
+		assert!(ranker.winnings("J2345 1").is_err());
+	}
+
+	#[test]
+	fn invalid_hands() {
+		for input in ["32T3K", "32T3K x", "32T3X 765"] {
+			assert!(part1(input).is_err(), "{input}");
+		}
+	}
+}
@@ -1,47 +1,77 @@
-use aoc_lib::{aoc, color_eyre::eyre::Result, iter::IterExt, to_lines};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, eyre, Result},
+	to_lines,
+};
 
 static INPUT: &str = include_str!("../../inputs/day09");
 
-fn extrapolate(d: impl Iterator<Item = i64>) -> i64 {
-	let mut p = d.peekable2();
+/// The lowest degree polynomial going through a sequence, in Newton's forward difference form:
+/// `p(x) = sum(diffs[j] * binomial(x, j))`, where `diffs[j]` is the first element
+/// of the j-th difference row and x = 0 is the first element of the sequence.
+struct Polynomial {
+	diffs: Vec<i128>,
+}
+
+impl Polynomial {
+	fn fit(nums: &[i128]) -> Result<Self> {
+		let mut diffs = Vec::new();
+		let mut row = nums.to_vec();
 
-	let drv: Vec<_> = std::iter::from_fn(|| match p.peek_pair() {
-		(Some(&a), Some(&b)) => {
-			p.next();
-			Some(b - a)
+		while !row.iter().all(|&n| n == 0) {
+			// a single element can't tell us anything about the next difference row
+			if row.len() < 2 {
+				bail!("sequence never reaches an all-zero difference row: {nums:?}");
+			}
+			diffs.push(row[0]);
+			row = row.windows(2).map(|w| w[1] - w[0]).collect();
 		}
-		_ => None,
-	})
-	.collect();
-
-	if drv.iter().all(|e| *e == 0) {
-		p.next().unwrap()
-	} else {
-		let last = p.next().unwrap();
-		last + extrapolate(drv.into_iter())
+
+		Ok(Polynomial { diffs })
 	}
-}
 
-fn part1(input: &str) -> Result<i64> {
-	let res = to_lines(input)
-		.map(|line| {
-			let nums = line.split_ascii_whitespace().map(|n| n.parse().unwrap());
-			extrapolate(nums)
-		})
-		.sum();
+	/// Value at any (also negative) position `x`.
+	fn at(&self, x: i128) -> Result<i128> {
+		let overflow = || eyre!("overflow evaluating at {x}");
+
+		let mut res: i128 = 0;
+		// binomial(x, j), which for negative x is still an integer
+		let mut binomial: i128 = 1;
+		for (j, &diff) in self.diffs.iter().enumerate() {
+			if j > 0 {
+				let j = j as i128;
+				// exact, since binomial(x, j) * j == binomial(x, j - 1) * (x - j + 1)
+				binomial = binomial.checked_mul(x - j + 1).ok_or_else(overflow)? / j;
+			}
+			res = diff
+				.checked_mul(binomial)
+				.and_then(|term| res.checked_add(term))
+				.ok_or_else(overflow)?;
+		}
+
+		Ok(res)
+	}
+}
 
-	Ok(res)
+fn parse(line: &str) -> Vec<i128> {
+	line.split_ascii_whitespace()
+		.map(|n| n.parse().unwrap())
+		.collect()
 }
 
-fn part2(input: &str) -> Result<i64> {
-	let res = to_lines(input)
+fn part1(input: &str) -> Result<i128> {
+	to_lines(input)
 		.map(|line| {
-			let nums = line.split_ascii_whitespace().map(|n| n.parse().unwrap());
-			extrapolate(nums.rev())
+			let nums = parse(line);
+			Polynomial::fit(&nums)?.at(nums.len() as i128)
 		})
-		.sum();
+		.sum()
+}
 
-	Ok(res)
+fn part2(input: &str) -> Result<i128> {
+	to_lines(input)
+		.map(|line| Polynomial::fit(&parse(line))?.at(-1))
+		.sum()
 }
 
 #[allow(dead_code)]
@@ -56,3 +86,61 @@ aoc! {
 	part1 => (EX_INPUT) 114,
 	part2 => (EX_INPUT) 2
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Extend the sequence forwards one difference row at a time, like in the puzzle.
+	fn extend(nums: &[i128], len: usize) -> Vec<i128> {
+		let mut rows = vec![nums.to_vec()];
+		while !rows.last().unwrap().iter().all(|&n| n == 0) {
+			let row = rows.last().unwrap();
+			rows.push(row.windows(2).map(|w| w[1] - w[0]).collect());
+		}
+		while rows[0].len() < len {
+			for i in (0..rows.len() - 1).rev() {
+				let next = rows[i].last().unwrap() + rows[i + 1].last().unwrap_or(&0);
+				rows[i].push(next);
+			}
+		}
+		rows.swap_remove(0)
+	}
+
+	#[test]
+	fn far_positions() {
+		let known = [
+			(&[0, 3, 6, 9, 12, 15][..], 300, -30),
+			// triangular numbers, (x + 1) * (x + 2) / 2
+			(&[1, 3, 6, 10, 15, 21][..], 5151, 36),
+		];
+		for (nums, at_100, at_minus_10) in known {
+			let poly = Polynomial::fit(nums).unwrap();
+			assert_eq!(poly.at(100).unwrap(), at_100, "{nums:?}");
+			assert_eq!(poly.at(-10).unwrap(), at_minus_10, "{nums:?}");
+		}
+
+		let nums = [10, 13, 16, 21, 30, 45];
+		let poly = Polynomial::fit(&nums).unwrap();
+		assert_eq!(poly.at(100).unwrap(), extend(&nums, 101)[100]);
+	}
+
+	#[test]
+	fn never_converges() {
+		assert!(Polynomial::fit(&[1, 2]).is_err());
+		assert!(part1("1 2").is_err());
+		assert!(part2("1 2").is_err());
+	}
+
+	#[test]
+	fn overflow() {
+		let poly = Polynomial::fit(&[0, 1, 4, 9]).unwrap();
+		assert_eq!(poly.at(1 << 40).unwrap(), 1 << 80);
+		assert!(poly.at(i128::MAX / 2).is_err());
+
+		let step = i128::MAX / 4;
+		let poly = Polynomial::fit(&[0, step, 2 * step]).unwrap();
+		assert_eq!(poly.at(3).unwrap(), 3 * step);
+		assert!(poly.at(5).is_err());
+	}
+}
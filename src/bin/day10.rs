@@ -2,7 +2,7 @@ use std::collections::{HashMap, HashSet};
 
 use aoc_lib::{
 	aoc,
-	color_eyre::eyre::Result,
+	color_eyre::eyre::{bail, Result},
 	grid::{Grid, Point, PointExt},
 	to_lines,
 };
@@ -59,11 +59,96 @@ fn find_main_loop(pipes: &HashMap<Point, Vec<Point>>, start: Point) -> HashSet<P
 	main_loop
 }
 
+/// Directions a pipe connects to.
+fn connections(c: u8) -> Option<[Point; 2]> {
+	match c {
+		b'|' => Some([(0, -1), (0, 1)]),
+		b'-' => Some([(-1, 0), (1, 0)]),
+		b'L' => Some([(0, -1), (1, 0)]),
+		b'J' => Some([(0, -1), (-1, 0)]),
+		b'7' => Some([(-1, 0), (0, 1)]),
+		b'F' => Some([(1, 0), (0, 1)]),
+		_ => None,
+	}
+}
+
+/// Follow the pipes from `start` through `first` and, if they lead back to `start`,
+/// return the tile they come back from.
+fn closes_loop(grid: Grid<'_>, start: Point, first: Point) -> Option<Point> {
+	let (mut prev, mut curr) = (start, first);
+	while curr != start {
+		let [a, b] = connections(*grid.get_pos(curr)?)?.map(|d| curr.add(&d));
+		let next = if a == prev {
+			b
+		} else if b == prev {
+			a
+		} else {
+			return None;
+		};
+		(prev, curr) = (curr, next);
+	}
+	Some(prev)
+}
+
+/// Figure out which pipe is hiding under `S`.
+/// Only the orthogonal neighbors pointing back at `S` are candidates,
+/// and if there are more than two of them the one pair which closes a loop wins.
+fn infer_start(grid: Grid<'_>, start: Point) -> Result<u8> {
+	let candidates: Vec<Point> = grid
+		.orthogonal_pos(start)
+		.filter(|&pos| {
+			grid.get_pos(pos)
+				.and_then(|&c| connections(c))
+				.is_some_and(|conns| conns.iter().any(|d| pos.add(d) == start))
+		})
+		.collect();
+
+	match candidates[..] {
+		[] => bail!("no pipe connects to S at {start:?}"),
+		[pos] => bail!("only the pipe at {pos:?} connects to S at {start:?}"),
+		_ => (),
+	}
+
+	let mut shapes: Vec<u8> = candidates
+		.iter()
+		.filter_map(|&first| {
+			let last = closes_loop(grid, start, first)?;
+			let dirs = [first, last].map(|pos| (pos.0 - start.0, pos.1 - start.1));
+			b"|-LJ7F".iter().copied().find(|&c| {
+				let conns = connections(c).unwrap();
+				dirs.iter().all(|d| conns.contains(d))
+			})
+		})
+		.collect();
+	shapes.sort_unstable();
+	shapes.dedup();
+
+	match shapes[..] {
+		[shape] => Ok(shape),
+		[] => bail!(
+			"no loop passes through S at {start:?}, candidates: {:?}",
+			candidates
+		),
+		_ => bail!(
+			"S at {start:?} is ambiguous, it could be any of: {}",
+			String::from_utf8_lossy(&shapes)
+		),
+	}
+}
+
+type Pipes = HashMap<Point, Vec<Point>>;
+
 // this is all useless but it was my first instinct to parse it this way, oh well
-fn parse_pipes(input: &str) -> (Point, HashMap<Point, Vec<Point>>, Point) {
+fn parse_pipes(input: &str) -> Result<(Point, Pipes, Point, u8)> {
 	let grid = Grid::for_str(input).unwrap();
 
-	let mut pipes: HashMap<_, Vec<_>> = to_lines(input)
+	let Some(start_idx) = input.trim().find('S') else {
+		bail!("no S in input");
+	};
+	let start_pos = grid.idx_to_pos(start_idx).unwrap();
+	let start_shape = infer_start(grid, start_pos)?;
+
+	let pipes: HashMap<_, Vec<_>> = to_lines(input)
 		.enumerate()
 		.flat_map(|(y, line)| {
 			let y = y as i64;
@@ -72,47 +157,29 @@ fn parse_pipes(input: &str) -> (Point, HashMap<Point, Vec<Point>>, Point) {
 				.enumerate()
 				.filter_map(move |(x, &c)| {
 					let pos = (x as i64, y);
-					let adj = |ds: [Point; 2]| {
-						ds.into_iter()
-							.map(|d| pos.add(&d))
-							.filter(|&pos| grid.is_valid_pos(pos))
-							.collect()
-					};
-					let v = match c {
+					let c = match c {
 						b'.' => return None,
-						b'|' => adj([(0, -1), (0, 1)]),
-						b'-' => adj([(-1, 0), (1, 0)]),
-						b'L' => adj([(0, -1), (1, 0)]),
-						b'J' => adj([(0, -1), (-1, 0)]),
-						b'7' => adj([(-1, 0), (0, 1)]),
-						b'F' => adj([(1, 0), (0, 1)]),
-						b'S' => Vec::new(),
-						c => panic!("Unexpected character in input: {}", c as char),
+						b'S' => start_shape,
+						c => c,
 					};
+					let Some(conns) = connections(c) else {
+						panic!("Unexpected character in input: {}", c as char);
+					};
+					let v = conns
+						.into_iter()
+						.map(|d| pos.add(&d))
+						.filter(|&pos| grid.is_valid_pos(pos))
+						.collect();
 					Some((pos, v))
 				})
 		})
 		.collect();
 
-	// find and fix up starting position
-	let start_idx = input.trim().find('S').unwrap();
-	let start_pos = grid.idx_to_pos(start_idx).unwrap();
-	let start_adj = grid
-		.adjacent_pos(start_pos)
-		.filter(|pos| {
-			pipes
-				.get(pos)
-				.map(|adj| adj.iter().any(|&pos| pos == start_pos))
-				.unwrap_or(false)
-		})
-		.collect();
-	*pipes.get_mut(&start_pos).unwrap() = start_adj;
-
-	((grid.width(), grid.height()), pipes, start_pos)
+	Ok(((grid.width(), grid.height()), pipes, start_pos, start_shape))
 }
 
 fn part1(input: &str) -> Result<i64> {
-	let (_, pipes, start_pos) = parse_pipes(input);
+	let (_, pipes, start_pos, _) = parse_pipes(input)?;
 
 	Ok(max_dist(&pipes, start_pos))
 }
@@ -132,26 +199,102 @@ fn is_cross((x, y): Point, pipes: &HashMap<Point, Vec<Point>>) -> bool {
 		.unwrap_or(false)
 }
 
-fn part2(input: &str) -> Result<i64> {
-	let ((width, height), pipes, start_pos) = parse_pipes(input);
-
-	let main_loop = find_main_loop(&pipes, start_pos);
-
-	let mut res = 0;
+fn find_enclosed(
+	(width, height): Point,
+	pipes: &HashMap<Point, Vec<Point>>,
+	main_loop: &HashSet<Point>,
+) -> HashSet<Point> {
+	let mut res = HashSet::new();
 	for y in 0..height {
 		let mut inside = false;
 		for x in 0..width {
 			if main_loop.contains(&(x, y)) {
-				if is_cross((x, y), &pipes) {
+				if is_cross((x, y), pipes) {
 					inside = !inside;
 				}
 			} else if inside {
-				res += 1;
+				res.insert((x, y));
+			}
+		}
+	}
+
+	res
+}
+
+/// Box-drawing character for a pipe, based on which sides it connects to.
+fn pipe_char(pos: Point, pipes: &HashMap<Point, Vec<Point>>) -> char {
+	let connects = |d: Point| pipes.get(&pos).unwrap().contains(&pos.add(&d));
+	match (
+		connects((0, -1)),
+		connects((0, 1)),
+		connects((-1, 0)),
+		connects((1, 0)),
+	) {
+		(true, true, _, _) => '│',
+		(_, _, true, true) => '─',
+		(true, _, _, true) => '└',
+		(true, _, true, _) => '┘',
+		(_, true, true, _) => '┐',
+		(_, true, _, true) => '┌',
+		_ => '?',
+	}
+}
+
+/// Redraw the map with only the main loop left,
+/// marking every other tile as either inside (I) or outside (O) of it.
+fn render(
+	(width, height): Point,
+	pipes: &HashMap<Point, Vec<Point>>,
+	main_loop: &HashSet<Point>,
+	enclosed: &HashSet<Point>,
+	ansi: bool,
+) -> String {
+	const LOOP_COLOR: &str = "\x1b[1;33m";
+	const INSIDE_COLOR: &str = "\x1b[1;32m";
+	const OUTSIDE_COLOR: &str = "\x1b[2;34m";
+	const RESET: &str = "\x1b[0m";
+
+	let mut res = String::new();
+	for y in 0..height {
+		for x in 0..width {
+			let (c, color) = if main_loop.contains(&(x, y)) {
+				(pipe_char((x, y), pipes), LOOP_COLOR)
+			} else if enclosed.contains(&(x, y)) {
+				('I', INSIDE_COLOR)
+			} else {
+				('O', OUTSIDE_COLOR)
+			};
+			if ansi {
+				res.push_str(color);
+				res.push(c);
+				res.push_str(RESET);
+			} else {
+				res.push(c);
 			}
 		}
+		res.push('\n');
+	}
+
+	res
+}
+
+fn part2(input: &str) -> Result<i64> {
+	let (size, pipes, start_pos, start_shape) = parse_pipes(input)?;
+
+	let main_loop = find_main_loop(&pipes, start_pos);
+	let enclosed = find_enclosed(size, &pipes, &main_loop);
+
+	match std::env::var("DAY10_RENDER").as_deref() {
+		Ok("text") => print!("{}", render(size, &pipes, &main_loop, &enclosed, false)),
+		Ok("ansi") => print!("{}", render(size, &pipes, &main_loop, &enclosed, true)),
+		Ok(format) => bail!("unknown render format: {format}"),
+		Err(_) => (),
+	}
+	if std::env::var_os("DAY10_RENDER").is_some() {
+		println!("S is {}", start_shape as char);
 	}
 
-	Ok(res)
+	Ok(enclosed.len() as i64)
 }
 
 #[allow(dead_code)]
@@ -199,3 +342,37 @@ aoc! {
 	part1 => (EX_INPUT_1) 8,
 	part2 => (EX_INPUT_2) crate::EX_INPUT_2_SOL
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn infer(input: &str) -> Result<u8> {
+		let grid = Grid::for_str(input).unwrap();
+		let start = grid.idx_to_pos(input.find('S').unwrap()).unwrap();
+		infer_start(grid, start)
+	}
+
+	fn infer_err(input: &str) -> String {
+		infer(input).unwrap_err().to_string()
+	}
+
+	#[test]
+	fn infers_start() {
+		assert_eq!(infer(".....\n.S-7.\n.|.|.\n.L-J.\n.....").unwrap(), b'F');
+		assert_eq!(infer("F-7\n|.|\nL-S").unwrap(), b'J');
+	}
+
+	#[test]
+	fn start_without_loop() {
+		assert!(infer_err("...\n.S.\n...").contains("no pipe connects"));
+		assert!(infer_err(".|.\n.S.\n...").contains("only the pipe"));
+		assert!(infer_err(".|.\n.S.\n.|.").contains("no loop"));
+	}
+
+	#[test]
+	fn ambiguous_start() {
+		let err = infer_err("F-7..\n|.|..\nL-S-7\n..|.|\n..L-J");
+		assert!(err.contains("ambiguous"), "{err}");
+	}
+}
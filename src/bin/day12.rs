@@ -1,120 +1,195 @@
-use std::collections::HashMap;
-
-use aoc_lib::{aoc, color_eyre::eyre::Result, to_lines};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, Result},
+	to_lines,
+};
 
 static INPUT: &str = include_str!("../../inputs/day12");
 
-// God bless @deliveroo
-// Wouldn't have solved this day without him
-// VERY heavily based on https://github.com/MarekSosnicki-deliveroo/AOC_2023/blob/master/examples/day_12_p2.rs
-fn test_str(s: &str, groups: &[usize]) -> usize {
-	fn recur(
-		s: &[u8],
-		groups: &[usize],
-		idx: usize,
-		mut group_idx: usize,
-		mut group_sz: usize,
-		cache: &mut HashMap<(usize, usize, usize), usize>,
-	) -> usize {
-		if let Some(&opts) = cache.get(&(idx, group_idx, group_sz)) {
-			return opts;
+struct Row {
+	springs: Vec<u8>,
+	groups: Vec<usize>,
+}
+
+impl Row {
+	fn parse(line: &str) -> Result<Self> {
+		let Some((springs, groups)) = line.split_once(' ') else {
+			bail!("invalid row: {line:?}");
+		};
+		if let Some(c) = springs.bytes().find(|c| !b".#?".contains(c)) {
+			bail!("unknown spring: {:?}", c as char);
 		}
-		for idx in idx..s.len() {
-			match s[idx] {
-				b'.' => {
-					if group_sz > 0 {
-						if groups.get(group_idx) != Some(&group_sz) {
-							return 0;
-						}
-						group_idx += 1;
-					}
-					group_sz = 0;
+		let groups = groups
+			.split(',')
+			.map(|n| n.parse())
+			.collect::<Result<_, _>>()?;
+		Ok(Row {
+			springs: springs.as_bytes().to_vec(),
+			groups,
+		})
+	}
+
+	/// The row repeated `k` times, with the copies of the springs separated by `?`.
+	fn unfold(&self, k: usize) -> Result<Self> {
+		if k == 0 {
+			bail!("the unfold factor has to be positive");
+		}
+		let springs = vec![self.springs.as_slice(); k].join(&b'?');
+		let groups = self.groups.repeat(k);
+		Ok(Row { springs, groups })
+	}
+}
+
+/// Number of ways to arrange the damaged springs of a row, along with the table it was
+/// computed from, which allows listing the actual arrangements.
+struct Arrangements<'a> {
+	row: &'a Row,
+	/// Number of arrangements of `springs[i..]` containing exactly `groups[g..]`, at `[i][g]`.
+	ways: Vec<Vec<u128>>,
+}
+
+impl<'a> Arrangements<'a> {
+	fn new(row: &'a Row) -> Self {
+		let (springs, groups) = (&row.springs, &row.groups);
+		let (n, m) = (springs.len(), groups.len());
+
+		// Length of the run of possibly damaged springs starting at every position.
+		let mut run = vec![0; n + 1];
+		for i in (0..n).rev() {
+			if springs[i] != b'.' {
+				run[i] = run[i + 1] + 1;
+			}
+		}
+
+		let mut ways = vec![vec![0u128; m + 1]; n + 1];
+		ways[n][m] = 1;
+		for i in (0..n).rev() {
+			for g in 0..=m {
+				let mut res = 0;
+				if springs[i] != b'#' {
+					res += ways[i + 1][g];
 				}
-				b'#' => {
-					group_sz += 1;
-					if let Some(&expected_sz) = groups.get(group_idx) {
-						if group_sz > expected_sz {
-							return 0;
-						}
-					} else {
-						return 0;
+				if let Some(&len) = groups.get(g) {
+					let end = i + len;
+					if run[i] >= len && (end == n || springs[end] != b'#') {
+						res += ways[(end + 1).min(n)][g + 1];
 					}
 				}
-				b'?' => {
-					// If current group size is 0 (i.e. we haven't started a group yet),
-					// continue with the current group index.
-					let new_group_idx = (group_sz == 0).then_some(group_idx).or_else(|| {
-						// Otherwise...
-						groups
-							.get(group_idx)
-							// ...if the current group has reached its desired size yet...
-							.filter(|&&sz| sz == group_sz)
-							// ...continue to the next group.
-							.map(|_| group_idx + 1)
-						// If it hasn't, we don't need to try replacing the current ? with .
-					});
-					let a = new_group_idx
-						.map(|group_idx| recur(s, groups, idx + 1, group_idx, 0, cache))
-						.unwrap_or(0);
-					let b = recur(s, groups, idx + 1, group_idx, group_sz + 1, cache);
-					let res = a + b;
-					cache.insert((idx, group_idx, group_sz), res);
-					return res;
-				}
-				_ => unreachable!(),
+				ways[i][g] = res;
 			}
 		}
 
-		if
-		// we are in a group
-		group_sz > 0
-		// the current group is the last one
-		&& group_idx == groups.len() - 1
-		// it is of the expected size
-		&& groups[group_idx] == group_sz
-		// or we aren't in a group and we have the corrent amount of groups
-		|| group_sz == 0 && group_idx == groups.len()
-		{
-			// That means we're in a corrent permutation
-			1
-		} else {
-			0
+		Arrangements { row, ways }
+	}
+
+	fn count(&self) -> u128 {
+		self.ways[0][0]
+	}
+
+	/// The arrangement with the given rank, in the order where operational springs come first.
+	fn nth(&self, mut rank: u128) -> Option<String> {
+		if rank >= self.count() {
+			return None;
 		}
+		let (springs, groups) = (&self.row.springs, &self.row.groups);
+		let n = springs.len();
+		let mut res = Vec::with_capacity(n);
+		let (mut i, mut g) = (0, 0);
+		while i < n {
+			let operational = if springs[i] != b'#' {
+				self.ways[i + 1][g]
+			} else {
+				0
+			};
+			if rank < operational {
+				res.push(b'.');
+				i += 1;
+				continue;
+			}
+			rank -= operational;
+			let end = i + groups[g];
+			res.resize(res.len() + groups[g], b'#');
+			if end < n {
+				res.push(b'.');
+			}
+			i = (end + 1).min(n);
+			g += 1;
+		}
+		String::from_utf8(res).ok()
 	}
 
-	let mut cache = HashMap::new();
-	recur(s.as_bytes(), groups, 0, 0, 0, &mut cache)
+	fn iter(&self) -> impl Iterator<Item = String> + '_ {
+		(0..self.count()).map_while(|rank| self.nth(rank))
+	}
+
+	fn sample(&self, rng: &mut SplitMix64) -> Option<String> {
+		self.nth(rng.below(self.count())?)
+	}
 }
 
-fn part1(input: &str) -> Result<usize> {
+/// Small deterministic generator, so that samples can be reproduced.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	/// Uniformly distributed number below `bound`, rejecting the values which would bias it.
+	fn below(&mut self, bound: u128) -> Option<u128> {
+		if bound == 0 {
+			return None;
+		}
+		let zone = u128::MAX - u128::MAX % bound;
+		loop {
+			let x = (self.next() as u128) << 64 | self.next() as u128;
+			if x < zone {
+				return Some(x % bound);
+			}
+		}
+	}
+}
+
+fn solve(input: &str, k: usize) -> Result<u128> {
+	let show = match std::env::var("DAY12_ROW") {
+		Ok(n) => Some(n.parse::<usize>()?),
+		Err(_) => None,
+	};
+	let mut rng = SplitMix64(0x2023_1212);
+	let mut res = 0;
+	for (i, line) in to_lines(input).enumerate() {
+		let row = Row::parse(line)?.unfold(k)?;
+		let arrangements = Arrangements::new(&row);
+		if show == Some(i + 1) {
+			const LIMIT: u128 = 20;
+			println!("row {}: {} arrangements", i + 1, arrangements.count());
+			if arrangements.count() <= LIMIT {
+				arrangements.iter().for_each(|a| println!("{a}"));
+			} else {
+				for _ in 0..LIMIT {
+					println!("{}", arrangements.sample(&mut rng).unwrap_or_default());
+				}
+			}
+		}
+		res += arrangements.count();
+	}
+	Ok(res)
+}
+
+fn part1(input: &str) -> Result<u128> {
 	let s = std::time::Instant::now();
-	let res = to_lines(input)
-		.map(|line| {
-			let (chars, nums) = line.split_once(' ').unwrap();
-			let nums: Vec<usize> = nums.split(',').map(|n| n.parse().unwrap()).collect();
-			test_str(chars, &nums)
-		})
-		.sum();
+	let res = solve(input, 1)?;
 	println!("part 1 took {}ms", s.elapsed().as_millis());
 	Ok(res)
 }
 
-fn part2(input: &str) -> Result<usize> {
+fn part2(input: &str) -> Result<u128> {
 	let s = std::time::Instant::now();
-	let res = to_lines(input)
-		.map(|line| {
-			let (c, nums) = line.split_once(' ').unwrap();
-			let mut nums: Vec<usize> = nums.split(',').map(|n| n.parse().unwrap()).collect();
-			let n = nums.clone();
-			let mut chars = c.to_string();
-			for _ in 0..4 {
-				nums.extend_from_slice(&n);
-				chars.push('?');
-				chars.push_str(c);
-			}
-			test_str(&chars, &nums)
-		})
-		.sum();
+	let res = solve(input, 5)?;
 	println!("part 2 took {}ms", s.elapsed().as_millis());
 	Ok(res)
 }
@@ -134,3 +209,90 @@ aoc! {
 	part1 => (EX_INPUT) 21,
 	part2 => (EX_INPUT) 525152
 }
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use super::*;
+
+	/// Tries every assignment of the unknown springs, only feasible for short rows.
+	/// Returns the arrangements in the same order as `Arrangements::iter`.
+	fn brute_force(row: &Row) -> Vec<String> {
+		let unknown: Vec<_> = (0..row.springs.len())
+			.filter(|&i| row.springs[i] == b'?')
+			.collect();
+		let mut springs = row.springs.clone();
+		let mut res: Vec<_> = (0..1u64 << unknown.len())
+			.filter_map(|mask| {
+				for (bit, &i) in unknown.iter().enumerate() {
+					springs[i] = if mask >> bit & 1 == 1 { b'#' } else { b'.' };
+				}
+				let groups: Vec<_> = springs
+					.split(|&c| c == b'.')
+					.map(<[u8]>::len)
+					.filter(|&len| len > 0)
+					.collect();
+				(groups == row.groups).then(|| String::from_utf8_lossy(&springs).into_owned())
+			})
+			.collect();
+		// '#' sorts before '.', so reversed lexicographic order puts operational springs first.
+		res.sort_unstable_by(|a, b| b.cmp(a));
+		res
+	}
+
+	/// Rows short enough to brute force, unfolded `k` times.
+	fn small_rows(k: usize) -> impl Iterator<Item = Row> {
+		to_lines(EX_INPUT)
+			.chain(to_lines(INPUT))
+			.map(move |line| Row::parse(line).unwrap().unfold(k).unwrap())
+			.filter(|row| row.springs.iter().filter(|&&c| c == b'?').count() <= 12)
+	}
+
+	#[test]
+	fn enumeration_matches_brute_force() {
+		for k in [1, 2] {
+			for row in small_rows(k) {
+				let arrangements = Arrangements::new(&row);
+				let expected = brute_force(&row);
+				assert_eq!(arrangements.count(), expected.len() as u128);
+				assert_eq!(arrangements.iter().collect::<Vec<_>>(), expected);
+				assert_eq!(arrangements.nth(arrangements.count()), None);
+			}
+		}
+	}
+
+	#[test]
+	fn samples_are_uniform_arrangements() {
+		let mut rng = SplitMix64(0x2023_1212);
+		for row in small_rows(1).take(50) {
+			let arrangements = Arrangements::new(&row);
+			let expected = brute_force(&row);
+			let mut seen = HashSet::new();
+			for _ in 0..20 * expected.len() {
+				let sample = arrangements.sample(&mut rng).unwrap();
+				assert!(expected.contains(&sample), "{sample}");
+				seen.insert(sample);
+			}
+			assert_eq!(seen.len(), expected.len());
+		}
+
+		let row = Row::parse("### 1").unwrap();
+		assert_eq!(Arrangements::new(&row).sample(&mut rng), None);
+		assert_eq!(rng.below(0), None);
+	}
+
+	#[test]
+	fn unfolding() {
+		let row = Row::parse("???.### 1,1,3").unwrap();
+		assert!(row.unfold(0).is_err());
+		let unfolded = row.unfold(2).unwrap();
+		assert_eq!(unfolded.springs, b"???.###????.###");
+		assert_eq!(unfolded.groups, [1, 1, 3, 1, 1, 3]);
+
+		let counts: Vec<_> = to_lines(EX_INPUT)
+			.map(|line| Arrangements::new(&Row::parse(line).unwrap().unfold(5).unwrap()).count())
+			.collect();
+		assert_eq!(counts, [1, 16384, 1, 16, 2500, 506250]);
+	}
+}
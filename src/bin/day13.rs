@@ -1,57 +1,135 @@
-use aoc_lib::{aoc, color_eyre::eyre::Result, grid::Grid, iter::IterExt};
+use std::fmt;
+
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, eyre, Result},
+	grid::{Grid, Point},
+};
 
 static INPUT: &str = include_str!("../../inputs/day13");
 
-fn solve(input: &str, allowed_smudges: usize) -> i64 {
-	input
-		.trim()
-		.split("\n\n")
-		.map(|group| {
-			let group = Grid::for_str(group).unwrap();
-
-			let reflection_x = (1..group.width())
-				.find(|&x| {
-					let x = x as usize;
-					group
-						.iter_rows()
-						.flat_map(|row| {
-							let left = row.iter().take(x);
-							let right = row.iter().skip(x);
-
-							left.rev().zip(right).filter(|(a, b)| a != b)
-						})
-						.has_n(allowed_smudges)
-				})
-				.unwrap_or(0);
-
-			let reflection_y = (1..group.height())
-				.find(|&y| {
-					let y = y as usize;
-					group
-						.iter_columns()
-						.flat_map(|column| {
-							let above = column.iter().take(y);
-							let below = column.iter().skip(y);
-
-							above.rev().zip(below).filter(|(a, b)| a != b)
-						})
-						.has_n(allowed_smudges)
-				})
-				.unwrap_or(0);
-
-			reflection_x + 100 * (reflection_y)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Axis {
+	/// Mirror between columns `x - 1` and `x`.
+	Vertical(i64),
+	/// Mirror between rows `y - 1` and `y`.
+	Horizontal(i64),
+}
+
+impl Axis {
+	fn summary(self) -> i64 {
+		match self {
+			Axis::Vertical(x) => x,
+			Axis::Horizontal(y) => 100 * y,
+		}
+	}
+}
+
+impl fmt::Display for Axis {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Axis::Vertical(x) => write!(f, "vertical after column {x}"),
+			Axis::Horizontal(y) => write!(f, "horizontal after row {y}"),
+		}
+	}
+}
+
+/// Pairs of cells mirrored by `axis` which aren't the same.
+fn mismatches(grid: &Grid, axis: Axis) -> Vec<(Point, Point)> {
+	let (w, h) = (grid.width(), grid.height());
+	let mirrored = |(x, y): Point| match axis {
+		Axis::Vertical(a) => (2 * a - 1 - x, y),
+		Axis::Horizontal(a) => (x, 2 * a - 1 - y),
+	};
+	let (xs, ys) = match axis {
+		Axis::Vertical(a) => (0..a, 0..h),
+		Axis::Horizontal(a) => (0..w, 0..a),
+	};
+	ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+		.map(|pos| (pos, mirrored(pos)))
+		.filter(|&(_, other)| grid.is_valid_pos(other))
+		.filter(|&(pos, other)| grid[pos] != grid[other])
+		.collect()
+}
+
+struct Reflection {
+	axis: Axis,
+	/// Every axis with at most the allowed number of smudges, with its number of smudges.
+	candidates: Vec<(Axis, usize)>,
+	/// Mirrored cells which differ across the axis; flipping either of them fixes the smudge.
+	smudges: Vec<(Point, Point)>,
+}
+
+impl Reflection {
+	fn find(grid: &Grid, allowed_smudges: usize) -> Result<Self> {
+		let axes = (1..grid.width())
+			.map(Axis::Vertical)
+			.chain((1..grid.height()).map(Axis::Horizontal));
+
+		let mut candidates = Vec::new();
+		let mut found = Vec::new();
+		for axis in axes {
+			let smudges = mismatches(grid, axis);
+			if smudges.len() > allowed_smudges {
+				continue;
+			}
+			candidates.push((axis, smudges.len()));
+			if smudges.len() == allowed_smudges {
+				found.push((axis, smudges));
+			}
+		}
+
+		if found.len() > 1 {
+			let axes = found
+				.iter()
+				.map(|(axis, _)| axis.to_string())
+				.collect::<Vec<_>>();
+			bail!("multiple reflections: {}", axes.join(", "));
+		}
+		let (axis, smudges) = found.pop().ok_or_else(|| eyre!("no reflection"))?;
+
+		Ok(Reflection {
+			axis,
+			candidates,
+			smudges,
 		})
-		.sum()
+	}
 }
 
-fn part1(input: &str) -> Result<i64> {
-	let res = solve(input, 0);
+impl fmt::Display for Reflection {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "reflection {}", self.axis)?;
+		for (axis, smudges) in &self.candidates {
+			writeln!(f, "  candidate {axis} with {smudges} smudge(s)")?;
+		}
+		for ((x1, y1), (x2, y2)) in &self.smudges {
+			writeln!(f, "  smudge at {x1},{y1} mirrored by {x2},{y2}")?;
+		}
+		Ok(())
+	}
+}
+
+fn solve(input: &str, allowed_smudges: usize) -> Result<i64> {
+	let report = std::env::var_os("DAY13_REPORT").is_some();
+	let mut res = 0;
+	for (i, group) in input.trim().split("\n\n").enumerate() {
+		let grid = Grid::for_str(group).ok_or_else(|| eyre!("invalid pattern {}", i + 1))?;
+		let reflection = Reflection::find(&grid, allowed_smudges)
+			.map_err(|e| eyre!("pattern {}: {e}", i + 1))?;
+		if report {
+			print!("pattern {}: {reflection}", i + 1);
+		}
+		res += reflection.axis.summary();
+	}
 	Ok(res)
 }
 
+fn part1(input: &str) -> Result<i64> {
+	solve(input, 0)
+}
+
 fn part2(input: &str) -> Result<i64> {
-	let res = solve(input, 1);
-	Ok(res)
+	solve(input, 1)
 }
 
 #[allow(dead_code)]
@@ -78,3 +156,42 @@ aoc! {
 	part1 => (EX_INPUT) 405,
 	part2 => (EX_INPUT) 400
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn find(pattern: &str, allowed_smudges: usize) -> Result<Reflection> {
+		Reflection::find(&Grid::for_str(pattern).unwrap(), allowed_smudges)
+	}
+
+	#[test]
+	fn reports_axis_and_smudge() {
+		let pattern = EX_INPUT.trim().split("\n\n").next().unwrap();
+
+		let clean = find(pattern, 0).unwrap();
+		assert_eq!(clean.axis, Axis::Vertical(5));
+		assert_eq!(clean.candidates, [(Axis::Vertical(5), 0)]);
+		assert!(clean.smudges.is_empty());
+
+		let smudged = find(pattern, 1).unwrap();
+		assert_eq!(smudged.axis, Axis::Horizontal(3));
+		assert!(smudged.candidates.contains(&(Axis::Vertical(5), 0)));
+		assert!(smudged.candidates.contains(&(Axis::Horizontal(3), 1)));
+		assert_eq!(smudged.smudges, [((0, 0), (0, 5))]);
+	}
+
+	#[test]
+	fn no_reflection() {
+		let err = find("#.\n.#", 0).err().unwrap().to_string();
+		assert!(err.contains("no reflection"), "{err}");
+	}
+
+	#[test]
+	fn multiple_reflections() {
+		let err = find("##\n##", 0).err().unwrap().to_string();
+		assert!(err.contains("multiple reflections"), "{err}");
+		let err = solve("#.\n#.\n\n##\n##", 0).unwrap_err().to_string();
+		assert!(err.starts_with("pattern 2"), "{err}");
+	}
+}
@@ -1,186 +1,226 @@
-use std::{collections::HashMap, ops::Range};
+use std::{
+	collections::hash_map::DefaultHasher,
+	hash::{Hash, Hasher},
+	ops::Range,
+	str::FromStr,
+};
 
-use aoc_lib::{aoc, color_eyre::eyre::Result};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, Report, Result},
+};
 
 static INPUT: &str = include_str!("../../inputs/day14");
 
-fn part1(input: &str) -> Result<usize> {
-	let input_str = input.trim();
-	let mut input = input_str.as_bytes().to_vec();
-	let line_width = input_str.lines().next().unwrap().len() + 1;
-	let height = input.len() / line_width + 1;
-	let width = line_width - 1;
-
-	for x in 0..width {
-		let mut last_free_y = None;
-		for y in 0..height {
-			let idx = y * line_width + x;
-			match input[idx] {
-				b'.' => {
-					if last_free_y.is_none() {
-						last_free_y = Some(y)
-					}
-				}
-				b'#' => last_free_y = None,
-				b'O' => {
-					if let Some(new_y) = last_free_y {
-						let new_idx = new_y * line_width + x;
-						input[new_idx] = b'O';
-						input[idx] = b'.';
-						last_free_y = Some(new_y + 1);
-					}
-				}
-				_ => unreachable!(),
-			};
-		}
-	}
+#[derive(Clone, Copy, Debug)]
+enum Direction {
+	North,
+	West,
+	South,
+	East,
+}
 
-	let mut res = 0;
-	for x in 0..width {
-		for y in 0..height {
-			let idx = y * line_width + x;
-			if input[idx] == b'O' {
-				res += height - y;
-			}
-		}
-	}
+const SPIN: [Direction; 4] = [
+	Direction::North,
+	Direction::West,
+	Direction::South,
+	Direction::East,
+];
 
-	Ok(res)
+/// Splits `0..len` into ranges which aren't interrupted by a cube rock.
+fn free_ranges(len: usize, is_free: impl Fn(usize) -> bool) -> Vec<Range<usize>> {
+	(0..len)
+		.filter(|&i| is_free(i))
+		.fold(Vec::<Range<usize>>::new(), |mut acc, curr| {
+			if let Some(last) = acc.last_mut().filter(|last| last.end == curr) {
+				last.end += 1;
+			} else {
+				acc.push(curr..curr + 1);
+			}
+			acc
+		})
 }
 
-fn part2(input: &str) -> Result<usize> {
-	const CYCLE_COUNT: usize = 1_000_000_000;
-	let input_str = input.trim();
-	let mut input = input_str.as_bytes().to_vec();
-	let line_width = input_str.lines().next().unwrap().len() + 1;
-	let height = input.len() / line_width + 1;
-	let width = line_width - 1;
-	let ranges_columns: Vec<_> = (0..width)
-		.map(|x| {
-			(0..height)
-				.filter(|&y| {
-					let idx = y * line_width + x;
-					input[idx] != b'#'
-				})
-				.fold(Vec::<Range<usize>>::new(), |mut acc, curr_y| {
-					if let Some(last) = acc.last_mut().filter(|last| last.end == curr_y) {
-						last.end += 1;
-					} else {
-						acc.push(curr_y..curr_y + 1);
-					}
-					acc
-				})
-		})
-		.collect();
-	let ranges_rows: Vec<_> = (0..height)
-		.map(|y| {
-			(0..width)
-				.filter(|&x| {
-					let idx = y * line_width + x;
-					input[idx] != b'#'
-				})
-				.fold(Vec::<Range<usize>>::new(), |mut acc, curr_x| {
-					if let Some(last) = acc.last_mut().filter(|last| last.end == curr_x) {
-						last.end += 1;
-					} else {
-						acc.push(curr_x..curr_x + 1);
-					}
-					acc
-				})
-		})
-		.collect();
+#[derive(Clone)]
+struct Platform {
+	width: usize,
+	height: usize,
+	cells: Vec<u8>,
+	/// Stretches of every column which the round rocks roll along.
+	columns: Vec<Vec<Range<usize>>>,
+	/// Stretches of every row which the round rocks roll along.
+	rows: Vec<Vec<Range<usize>>>,
+}
 
-	let mut cache = HashMap::<Vec<u8>, usize>::new();
+impl FromStr for Platform {
+	type Err = Report;
 
-	let mut i = 0;
-	loop {
-		if i >= CYCLE_COUNT {
-			break;
+	fn from_str(s: &str) -> Result<Self> {
+		let lines: Vec<_> = s.trim().lines().collect();
+		let height = lines.len();
+		let width = lines.first().map_or(0, |line| line.len());
+		if lines.iter().any(|line| line.len() != width) {
+			bail!("platform rows have different lengths");
 		}
-		if let Some(last_seen) = cache.get(&input) {
-			let d = i - last_seen;
-			if i + d < CYCLE_COUNT - 1 {
-				i += d;
-				continue;
-			}
+		let cells: Vec<_> = lines.concat().into_bytes();
+		if let Some(c) = cells.iter().find(|c| !b".#O".contains(c)) {
+			bail!("unknown tile: {:?}", *c as char);
 		}
 
-		cache.insert(input.clone(), i);
+		let columns = (0..width)
+			.map(|x| free_ranges(height, |y| cells[y * width + x] != b'#'))
+			.collect();
+		let rows = (0..height)
+			.map(|y| free_ranges(width, |x| cells[y * width + x] != b'#'))
+			.collect();
 
-		for (x, ranges) in ranges_columns.iter().enumerate() {
+		Ok(Platform {
+			width,
+			height,
+			cells,
+			columns,
+			rows,
+		})
+	}
+}
+
+impl Platform {
+	/// Rolls every round rock as far as it goes towards `dir`.
+	fn tilt(&mut self, dir: Direction) {
+		let (lines, stride, step) = match dir {
+			Direction::North | Direction::South => (&self.columns, 1, self.width),
+			Direction::West | Direction::East => (&self.rows, self.width, 1),
+		};
+		let towards_start = matches!(dir, Direction::North | Direction::West);
+
+		for (line, ranges) in lines.iter().enumerate() {
+			let idx = |i: usize| line * stride + i * step;
 			for range in ranges {
 				let rocks = range
 					.clone()
-					.filter(|&y| input[y * line_width + x] == b'O')
+					.filter(|&i| self.cells[idx(i)] == b'O')
 					.count();
-				for y in range.start..range.start + rocks {
-					input[y * line_width + x] = b'O';
-				}
-				for y in range.start + rocks..range.end {
-					input[y * line_width + x] = b'.';
+				let split = if towards_start {
+					range.start + rocks
+				} else {
+					range.end - rocks
+				};
+				for i in range.clone() {
+					let rock = (i < split) == towards_start;
+					self.cells[idx(i)] = if rock { b'O' } else { b'.' };
 				}
 			}
 		}
+	}
 
-		for (y, ranges) in ranges_rows.iter().enumerate() {
-			for range in ranges {
-				let rocks = range
-					.clone()
-					.filter(|&x| input[y * line_width + x] == b'O')
-					.count();
-				for x in range.start..range.start + rocks {
-					input[y * line_width + x] = b'O';
-				}
-				for x in range.start + rocks..range.end {
-					input[y * line_width + x] = b'.';
-				}
-			}
+	fn spin(&mut self) {
+		for dir in SPIN {
+			self.tilt(dir);
 		}
+	}
 
-		for (x, ranges) in ranges_columns.iter().enumerate() {
-			for range in ranges {
-				let rocks = range
-					.clone()
-					.filter(|&y| input[y * line_width + x] == b'O')
-					.count();
-				for y in range.end - rocks..range.end {
-					input[y * line_width + x] = b'O';
-				}
-				for y in range.start..range.end - rocks {
-					input[y * line_width + x] = b'.';
-				}
-			}
+	/// Total load on the north support beams.
+	fn load(&self) -> usize {
+		self.cells
+			.chunks(self.width)
+			.enumerate()
+			.map(|(y, row)| (self.height - y) * row.iter().filter(|&&c| c == b'O').count())
+			.sum()
+	}
+
+	fn state_hash(&self) -> u64 {
+		let mut hasher = DefaultHasher::new();
+		self.cells.hash(&mut hasher);
+		hasher.finish()
+	}
+
+	fn find_cycle(&self) -> Cycle {
+		Cycle::find(self, Platform::spin, Platform::state_hash)
+	}
+
+	/// Load after `n` spin cycles, skipping over the repeating part with `cycle`.
+	fn load_after(&self, cycle: &Cycle, n: usize) -> usize {
+		let mut platform = self.clone();
+		for _ in 0..cycle.reduce(n) {
+			platform.spin();
 		}
+		platform.load()
+	}
+}
 
-		for (y, ranges) in ranges_rows.iter().enumerate() {
-			for range in ranges {
-				let rocks = range
-					.clone()
-					.filter(|&x| input[y * line_width + x] == b'O')
-					.count();
-				for x in range.end - rocks..range.end {
-					input[y * line_width + x] = b'O';
-				}
-				for x in range.start..range.end - rocks {
-					input[y * line_width + x] = b'.';
-				}
+/// Shape of the sequence x, f(x), f(f(x)), ... which eventually repeats.
+struct Cycle {
+	/// Index of the first state of the loop.
+	offset: usize,
+	/// Length of the loop.
+	period: usize,
+}
+
+impl Cycle {
+	/// Brent's algorithm, comparing the states by `key` only.
+	fn find<T: Clone, K: PartialEq>(
+		start: &T,
+		step: impl Fn(&mut T),
+		key: impl Fn(&T) -> K,
+	) -> Self {
+		let advance = |mut x: T| {
+			step(&mut x);
+			x
+		};
+
+		let mut power = 1;
+		let mut period = 1;
+		let mut tortoise = start.clone();
+		let mut hare = advance(start.clone());
+		while key(&tortoise) != key(&hare) {
+			if power == period {
+				tortoise = hare.clone();
+				power *= 2;
+				period = 0;
 			}
+			hare = advance(hare);
+			period += 1;
+		}
+
+		let mut offset = 0;
+		let mut tortoise = start.clone();
+		let mut hare = (0..period).fold(start.clone(), |x, _| advance(x));
+		while key(&tortoise) != key(&hare) {
+			tortoise = advance(tortoise);
+			hare = advance(hare);
+			offset += 1;
 		}
 
-		i += 1;
+		Cycle { offset, period }
 	}
 
-	let mut res = 0;
-	for x in 0..width {
-		for y in 0..height {
-			let idx = y * line_width + x;
-			if input[idx] == b'O' {
-				res += height - y;
-			}
+	/// Smallest number of steps ending in the same state as `n` steps.
+	fn reduce(&self, n: usize) -> usize {
+		if n < self.offset {
+			n
+		} else {
+			self.offset + (n - self.offset) % self.period
 		}
 	}
+}
+
+fn part1(input: &str) -> Result<usize> {
+	let mut platform: Platform = input.parse()?;
+	platform.tilt(Direction::North);
+	Ok(platform.load())
+}
 
-	Ok(res)
+fn part2(input: &str) -> Result<usize> {
+	const CYCLE_COUNT: usize = 1_000_000_000;
+	let platform: Platform = input.parse()?;
+	let cycle = platform.find_cycle();
+	if std::env::var_os("DAY14_CYCLE").is_some() {
+		println!(
+			"spins repeat every {} cycles after the first {}",
+			cycle.period, cycle.offset
+		);
+	}
+	Ok(platform.load_after(&cycle, CYCLE_COUNT))
 }
 
 #[allow(dead_code)]
@@ -202,3 +242,93 @@ aoc! {
 	part1 => (EX_INPUT) 136,
 	part2 => (EX_INPUT) 64
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn grid(platform: &Platform) -> String {
+		platform
+			.cells
+			.chunks(platform.width)
+			.map(|row| String::from_utf8_lossy(row).into_owned() + "\n")
+			.collect()
+	}
+
+	/// Move the round rocks a single tile at a time until none of them can move.
+	fn roll(input: &str, (dx, dy): (i64, i64)) -> String {
+		let mut rows: Vec<Vec<u8>> = input.trim().lines().map(|line| line.into()).collect();
+		let (width, height) = (rows[0].len() as i64, rows.len() as i64);
+		let mut moved = true;
+		while moved {
+			moved = false;
+			for y in 0..height {
+				for x in 0..width {
+					let (nx, ny) = (x + dx, y + dy);
+					if rows[y as usize][x as usize] != b'O'
+						|| !(0..width).contains(&nx)
+						|| !(0..height).contains(&ny)
+						|| rows[ny as usize][nx as usize] != b'.'
+					{
+						continue;
+					}
+					rows[ny as usize][nx as usize] = b'O';
+					rows[y as usize][x as usize] = b'.';
+					moved = true;
+				}
+			}
+		}
+		rows.into_iter()
+			.map(|row| String::from_utf8_lossy(&row).into_owned() + "\n")
+			.collect()
+	}
+
+	#[test]
+	fn tilts() {
+		let mut platform: Platform = EX_INPUT.parse().unwrap();
+		platform.tilt(Direction::North);
+		assert_eq!(
+			grid(&platform),
+			"OOOO.#.O..\nOO..#....#\nOO..O##..O\nO..#.OO...\n........#.\n\
+			 ..#....#.#\n..O..#.O.O\n..O.......\n#....###..\n#....#....\n"
+		);
+
+		for (dir, diff) in [
+			(Direction::North, (0, -1)),
+			(Direction::West, (-1, 0)),
+			(Direction::South, (0, 1)),
+			(Direction::East, (1, 0)),
+		] {
+			let mut platform: Platform = EX_INPUT.parse().unwrap();
+			platform.tilt(dir);
+			assert_eq!(grid(&platform), roll(EX_INPUT, diff), "{dir:?}");
+		}
+	}
+
+	#[test]
+	fn spins() {
+		let after = [
+			".....#....\n....#...O#\n...OO##...\n.OO#......\n.....OOO#.\n\
+			 .O#...O#.#\n....O#....\n......OOOO\n#...O###..\n#..OO#....\n",
+			".....#....\n....#...O#\n.....##...\n..O#......\n.....OOO#.\n\
+			 .O#...O#.#\n....O#...O\n.......OOO\n#..OO###..\n#.OOO#...O\n",
+			".....#....\n....#...O#\n.....##...\n..O#......\n.....OOO#.\n\
+			 .O#...O#.#\n....O#...O\n.......OOO\n#...O###.O\n#.OOO#...O\n",
+		];
+		let mut platform: Platform = EX_INPUT.parse().unwrap();
+		for (i, expected) in after.into_iter().enumerate() {
+			platform.spin();
+			assert_eq!(grid(&platform), expected, "after {} cycles", i + 1);
+		}
+	}
+
+	#[test]
+	fn cycle() {
+		let platform: Platform = EX_INPUT.parse().unwrap();
+		let cycle = platform.find_cycle();
+		assert_eq!((cycle.offset, cycle.period), (3, 7));
+		assert_eq!(cycle.reduce(2), 2);
+		assert_eq!(cycle.reduce(10), 3);
+		assert_eq!(cycle.reduce(1_000_000_000), 6);
+	}
+}
@@ -1,65 +1,129 @@
-use aoc_lib::{aoc, color_eyre::eyre::Result};
+use std::fmt;
+
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, Result},
+};
 
 static INPUT: &str = include_str!("../../inputs/day15");
 
+/// The Holiday ASCII String Helper algorithm.
+fn hash(s: &[u8]) -> u8 {
+	s.iter()
+		.fold(0u8, |acc, &curr| acc.wrapping_add(curr).wrapping_mul(17))
+}
+
+/// The Holiday ASCII String Helper Manual Arrangement Procedure: 256 boxes of lenses,
+/// each keeping its lenses in insertion order.
+struct Hashmap<'a> {
+	hasher: fn(&[u8]) -> u8,
+	boxes: Vec<Vec<(&'a str, usize)>>,
+}
+
+impl<'a> Hashmap<'a> {
+	fn new(hasher: fn(&[u8]) -> u8) -> Self {
+		Hashmap {
+			hasher,
+			boxes: vec![Vec::new(); 256],
+		}
+	}
+
+	fn lenses(&self, label: &str) -> &Vec<(&'a str, usize)> {
+		&self.boxes[(self.hasher)(label.as_bytes()) as usize]
+	}
+
+	fn lenses_mut(&mut self, label: &str) -> &mut Vec<(&'a str, usize)> {
+		&mut self.boxes[(self.hasher)(label.as_bytes()) as usize]
+	}
+
+	/// Replaces the lens with the same label in place, or puts the new one at the back of its box.
+	/// Returns the focal length of the replaced lens.
+	fn insert(&mut self, label: &'a str, focal: usize) -> Option<usize> {
+		let lenses = self.lenses_mut(label);
+		if let Some((_, old)) = lenses.iter_mut().find(|(l, _)| *l == label) {
+			return Some(std::mem::replace(old, focal));
+		}
+		lenses.push((label, focal));
+		None
+	}
+
+	fn remove(&mut self, label: &str) -> Option<usize> {
+		let lenses = self.lenses_mut(label);
+		let idx = lenses.iter().position(|(l, _)| *l == label)?;
+		Some(lenses.remove(idx).1)
+	}
+
+	#[allow(dead_code)]
+	fn get(&self, label: &str) -> Option<usize> {
+		self.lenses(label)
+			.iter()
+			.find(|(l, _)| *l == label)
+			.map(|&(_, focal)| focal)
+	}
+
+	/// All the lenses as (box, slot, label, focal length), ordered by box and then by slot.
+	fn iter(&self) -> impl Iterator<Item = (usize, usize, &'a str, usize)> + '_ {
+		self.boxes.iter().enumerate().flat_map(|(b, lenses)| {
+			lenses
+				.iter()
+				.enumerate()
+				.map(move |(slot, &(label, focal))| (b, slot, label, focal))
+		})
+	}
+
+	fn focusing_power(&self) -> usize {
+		self.iter()
+			.map(|(b, slot, _, focal)| (b + 1) * (slot + 1) * focal)
+			.sum()
+	}
+}
+
+impl fmt::Display for Hashmap<'_> {
+	/// Contents of the non-empty boxes, the way the puzzle text shows them.
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (b, lenses) in self.boxes.iter().enumerate() {
+			if lenses.is_empty() {
+				continue;
+			}
+			write!(f, "Box {b}:")?;
+			for (label, focal) in lenses {
+				write!(f, " [{label} {focal}]")?;
+			}
+			writeln!(f)?;
+		}
+		Ok(())
+	}
+}
+
 fn part1(input: &str) -> Result<u64> {
 	let res = input
 		.trim()
 		.split(',')
-		.map(|chars| {
-			chars
-				.as_bytes()
-				.iter()
-				.fold(0u64, |acc, &curr| ((acc + curr as u64) * 17) % 256)
-		})
+		.map(|chars| hash(chars.as_bytes()) as u64)
 		.sum();
 
 	Ok(res)
 }
 
 fn part2(input: &str) -> Result<usize> {
-	const NEW_VEC: Vec<(Vec<u8>, usize)> = Vec::new();
-	let mut b = [NEW_VEC; 256];
-
-	for chars in input.trim().split(',') {
-		let mut chars = chars.as_bytes().iter().peekable();
-		let label: Vec<_> = std::iter::from_fn(|| chars.next_if(|x| x.is_ascii_alphabetic()))
-			.copied()
-			.collect();
-		let idx = label
-			.iter()
-			.fold(0u8, |acc, &curr| acc.wrapping_add(curr).wrapping_mul(17)) as usize;
+	let steps = std::env::var_os("DAY15_STEPS").is_some();
+	let mut map = Hashmap::new(hash);
 
-		match chars.next().unwrap() {
-			b'-' => {
-				if let Some(inner_idx) = b[idx].iter().position(|(v, _)| v == &label) {
-					b[idx].remove(inner_idx);
-				}
-			}
-			b'=' => {
-				let val = chars.fold(0, |acc, &curr| acc * 10 + (curr - b'0') as usize);
-				if let Some(inner_idx) = b[idx].iter().position(|(v, _)| v == &label) {
-					b[idx][inner_idx].1 = val;
-				} else {
-					b[idx].push((label, val));
-				}
-			}
-			_ => unreachable!(),
+	for step in input.trim().split(',') {
+		if let Some(label) = step.strip_suffix('-') {
+			map.remove(label);
+		} else if let Some((label, focal)) = step.split_once('=') {
+			map.insert(label, focal.parse()?);
+		} else {
+			bail!("invalid step: {step:?}");
 		}
-	}
 
-	let res = b
-		.iter()
-		.enumerate()
-		.map(|(i, b)| {
-			b.iter()
-				.enumerate()
-				.map(|(j, (_, v))| (i + 1) * (j + 1) * v)
-				.sum::<usize>()
-		})
-		.sum();
+		if steps {
+			println!("After \"{step}\":\n{map}");
+		}
+	}
 
-	Ok(res)
+	Ok(map.focusing_power())
 }
 
 #[allow(dead_code)]
@@ -72,3 +136,43 @@ aoc! {
 	part1 => (EX_INPUT) 1320,
 	part2 => (EX_INPUT) 145
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn hashmap_operations() {
+		let mut map = Hashmap::new(hash);
+		assert_eq!(map.insert("rn", 1), None);
+		assert_eq!(map.to_string(), "Box 0: [rn 1]\n");
+		assert_eq!(map.insert("cm", 3), None);
+		assert_eq!(map.insert("cm", 2), Some(3));
+		assert_eq!(map.insert("ot", 9), None);
+		assert_eq!(map.remove("qp"), None);
+		assert_eq!(map.get("cm"), Some(2));
+		assert_eq!(map.get("qp"), None);
+		assert_eq!(
+			map.iter().collect::<Vec<_>>(),
+			[(0, 0, "rn", 1), (0, 1, "cm", 2), (3, 0, "ot", 9)]
+		);
+		assert_eq!(map.remove("rn"), Some(1));
+		assert_eq!(map.to_string(), "Box 0: [cm 2]\nBox 3: [ot 9]\n");
+		assert_eq!(map.focusing_power(), 2 + 4 * 9);
+	}
+
+	#[test]
+	fn custom_hasher() {
+		let mut map = Hashmap::new(|_| 7);
+		map.insert("a", 1);
+		map.insert("b", 2);
+		assert_eq!(map.to_string(), "Box 7: [a 1] [b 2]\n");
+		assert_eq!(map.focusing_power(), 8 * (1 + 2 * 2));
+	}
+
+	#[test]
+	fn invalid_step() {
+		assert!(part2("rn=1,cm").is_err());
+		assert!(part2("rn=x").is_err());
+	}
+}
@@ -1,87 +1,414 @@
-use std::collections::HashSet;
+use std::{
+	collections::{HashMap, HashSet},
+	path::{Path, PathBuf},
+};
 
-use aoc_lib::{aoc, color_eyre::eyre::Result, grid::Grid};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, eyre, Result},
+	grid::{Grid, Point},
+};
 
 static INPUT: &str = include_str!("../../inputs/day16");
 
-fn laser(grid: Grid<'_>, direction: (i64, i64), start_pos: (i64, i64)) -> usize {
-	let mut cycles = HashSet::new();
-	let mut dirs_pos = Vec::<((i64, i64), (i64, i64))>::new();
-	dirs_pos.push((direction, start_pos));
+type Dir = (i64, i64);
 
-	while dirs_pos.iter().any(|&(_, pos)| grid.is_valid_pos(pos)) {
-		for i in (0..dirs_pos.len()).rev() {
-			let (dir, pos) = dirs_pos[i];
-			if !grid.is_valid_pos(pos) || cycles.contains(&(dir, pos)) {
-				continue;
-			}
-			cycles.insert((dir, pos));
-
-			match grid.get_pos(pos).unwrap() {
-				b'.' => (),
-				b'-' if dir.1 == 0 => (),
-				b'|' if dir.0 == 0 => (),
-				b'\\' => {
-					dirs_pos[i].0 = (dir.1, dir.0);
+const DIRS: [Dir; 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+/// Directions the beam leaves `tile` in after entering it going `dir`.
+fn outgoing(tile: u8, dir: Dir) -> Result<Vec<Dir>> {
+	let res = match tile {
+		b'.' => vec![dir],
+		b'-' if dir.1 == 0 => vec![dir],
+		b'|' if dir.0 == 0 => vec![dir],
+		b'\\' => vec![(dir.1, dir.0)],
+		b'/' => vec![(-dir.1, -dir.0)],
+		b'-' | b'|' => vec![(dir.1, dir.0), (-dir.1, -dir.0)],
+		c => bail!("unknown tile: {:?}", c as char),
+	};
+	Ok(res)
+}
+
+/// Fixed-size set of tiles.
+#[derive(Clone)]
+struct TileSet(Vec<u64>);
+
+impl TileSet {
+	fn new(size: usize) -> Self {
+		TileSet(vec![0; size.div_ceil(64)])
+	}
+
+	fn insert(&mut self, tile: usize) {
+		self.0[tile / 64] |= 1 << (tile % 64);
+	}
+
+	fn union(&mut self, other: &TileSet) {
+		for (a, b) in self.0.iter_mut().zip(&other.0) {
+			*a |= b;
+		}
+	}
+
+	fn len(&self) -> usize {
+		self.0.iter().map(|w| w.count_ones() as usize).sum()
+	}
+}
+
+/// Every state of a beam, that is a tile along with the direction the beam enters it in,
+/// linked to the states it immediately leads to.
+struct BeamGraph {
+	width: i64,
+	height: i64,
+	next: Vec<Vec<usize>>,
+}
+
+impl BeamGraph {
+	fn new(grid: &Grid) -> Result<Self> {
+		let (width, height) = (grid.width(), grid.height());
+		let mut next = Vec::with_capacity((width * height) as usize * DIRS.len());
+		for y in 0..height {
+			for x in 0..width {
+				for dir in DIRS {
+					let mut states = Vec::new();
+					for new_dir in outgoing(grid[(x, y)], dir)? {
+						let pos = (x + new_dir.0, y + new_dir.1);
+						if grid.is_valid_pos(pos) {
+							states.push(Self::state_id(width, pos, new_dir));
+						}
+					}
+					next.push(states);
 				}
-				b'/' => {
-					dirs_pos[i].0 = (-dir.1, -dir.0);
+			}
+		}
+		Ok(BeamGraph {
+			width,
+			height,
+			next,
+		})
+	}
+
+	fn state_id(width: i64, (x, y): Point, dir: Dir) -> usize {
+		let d = DIRS.iter().position(|&d| d == dir).unwrap();
+		(y * width + x) as usize * DIRS.len() + d
+	}
+
+	fn id(&self, pos: Point, dir: Dir) -> usize {
+		Self::state_id(self.width, pos, dir)
+	}
+
+	fn tile(state: usize) -> usize {
+		state / DIRS.len()
+	}
+
+	fn pos(&self, state: usize) -> Point {
+		let tile = Self::tile(state) as i64;
+		(tile % self.width, tile / self.width)
+	}
+
+	fn dir(state: usize) -> Dir {
+		DIRS[state % DIRS.len()]
+	}
+
+	/// States a beam starting in `start` reaches, grouped by the step in which it first reaches them.
+	fn trace(&self, start: usize) -> Vec<Vec<usize>> {
+		let mut seen = vec![false; self.next.len()];
+		seen[start] = true;
+		let mut steps = vec![vec![start]];
+		loop {
+			let mut frontier = Vec::new();
+			for &state in steps.last().unwrap() {
+				for &next in &self.next[state] {
+					if !seen[next] {
+						seen[next] = true;
+						frontier.push(next);
+					}
 				}
-				b'-' | b'|' => {
-					dirs_pos.remove(i);
-					let new_dir = (dir.1, dir.0);
-					let new_pos = (pos.0 + new_dir.0, pos.1 + new_dir.1);
-					dirs_pos.insert(i, (new_dir, new_pos));
+			}
+			if frontier.is_empty() {
+				break steps;
+			}
+			steps.push(frontier);
+		}
+	}
 
-					let new_dir = (-dir.1, -dir.0);
-					let new_pos = (pos.0 + new_dir.0, pos.1 + new_dir.1);
-					dirs_pos.insert(i, (new_dir, new_pos));
+	/// Tiles energized by a beam starting in `start`.
+	fn energized_set(&self, start: usize) -> HashSet<Point> {
+		self.trace(start)
+			.into_iter()
+			.flatten()
+			.map(|state| self.pos(state))
+			.collect()
+	}
 
+	/// Tarjan's algorithm, without recursion. Returns the component of every state,
+	/// with components numbered so that every edge goes to the same or a lower one.
+	fn components(&self) -> (Vec<usize>, usize) {
+		const UNSET: usize = usize::MAX;
+		let n = self.next.len();
+		let mut index = vec![UNSET; n];
+		let mut low = vec![0; n];
+		let mut on_stack = vec![false; n];
+		let mut stack = Vec::new();
+		let mut comp = vec![UNSET; n];
+		let mut comp_count = 0;
+		let mut counter = 0;
+
+		for root in 0..n {
+			if index[root] != UNSET {
+				continue;
+			}
+			let mut calls = vec![(root, 0)];
+			index[root] = counter;
+			low[root] = counter;
+			counter += 1;
+			stack.push(root);
+			on_stack[root] = true;
+
+			while let Some((v, edge)) = calls.last_mut() {
+				let v = *v;
+				if let Some(&w) = self.next[v].get(*edge) {
+					*edge += 1;
+					if index[w] == UNSET {
+						index[w] = counter;
+						low[w] = counter;
+						counter += 1;
+						stack.push(w);
+						on_stack[w] = true;
+						calls.push((w, 0));
+					} else if on_stack[w] {
+						low[v] = low[v].min(index[w]);
+					}
 					continue;
 				}
-				&c => unreachable!("char: {}", c as char),
+
+				calls.pop();
+				if let Some(&(parent, _)) = calls.last() {
+					low[parent] = low[parent].min(low[v]);
+				}
+				if low[v] == index[v] {
+					while let Some(w) = stack.pop() {
+						on_stack[w] = false;
+						comp[w] = comp_count;
+						if w == v {
+							break;
+						}
+					}
+					comp_count += 1;
+				}
 			}
+		}
+
+		(comp, comp_count)
+	}
+
+	/// Number of tiles energized by a beam starting in every state.
+	///
+	/// Tiles are gathered per strongly connected component and pushed up the condensed graph,
+	/// one level at a time. A component's set is only kept until all its predecessors are done.
+	/// Components within a level don't depend on each other, so big levels are split
+	/// between `threads` threads.
+	fn energized(&self, threads: usize) -> Vec<usize> {
+		/// Fewer components than this aren't worth spawning threads for.
+		const PARALLEL_LEVEL: usize = 512;
+
+		let tiles = (self.width * self.height) as usize;
+		let (comp, comp_count) = self.components();
 
-			dirs_pos[i].1 = (pos.0 + dirs_pos[i].0 .0, pos.1 + dirs_pos[i].0 .1);
+		let mut own = vec![Vec::new(); comp_count];
+		let mut succ = vec![Vec::new(); comp_count];
+		for (state, next) in self.next.iter().enumerate() {
+			let c = comp[state];
+			own[c].push(Self::tile(state));
+			succ[c].extend(next.iter().map(|&s| comp[s]).filter(|&s| s != c));
 		}
-		for i in (0..dirs_pos.len()).rev() {
-			if !grid.is_valid_pos(dirs_pos[i].1) || cycles.contains(&dirs_pos[i]) {
-				dirs_pos.remove(i);
+		let mut pending_preds = vec![0; comp_count];
+		let mut levels = Vec::<Vec<usize>>::new();
+		let mut level = vec![0; comp_count];
+		for c in 0..comp_count {
+			succ[c].sort_unstable();
+			succ[c].dedup();
+			for &s in &succ[c] {
+				pending_preds[s] += 1;
 			}
+			level[c] = succ[c].iter().map(|&s| level[s] + 1).max().unwrap_or(0);
+			if levels.len() <= level[c] {
+				levels.push(Vec::new());
+			}
+			levels[level[c]].push(c);
 		}
+
+		let mut sets: Vec<Option<TileSet>> = vec![None; comp_count];
+		let mut counts = vec![0; comp_count];
+		for comps in levels {
+			let reach = |c: usize| {
+				let mut set = TileSet::new(tiles);
+				for &tile in &own[c] {
+					set.insert(tile);
+				}
+				for &s in &succ[c] {
+					set.union(sets[s].as_ref().expect("successor set was freed too early"));
+				}
+				(c, set)
+			};
+			let done: Vec<(usize, TileSet)> = if threads > 1 && comps.len() >= PARALLEL_LEVEL {
+				let chunk = comps.len().div_ceil(threads);
+				std::thread::scope(|scope| {
+					let handles: Vec<_> = comps
+						.chunks(chunk)
+						.map(|chunk| {
+							scope.spawn(|| chunk.iter().map(|&c| reach(c)).collect::<Vec<_>>())
+						})
+						.collect();
+					handles
+						.into_iter()
+						.flat_map(|h| h.join().unwrap())
+						.collect()
+				})
+			} else {
+				comps.iter().map(|&c| reach(c)).collect()
+			};
+
+			for (c, set) in done {
+				counts[c] = set.len();
+				if pending_preds[c] > 0 {
+					sets[c] = Some(set);
+				}
+				for &s in &succ[c] {
+					pending_preds[s] -= 1;
+					if pending_preds[s] == 0 {
+						sets[s] = None;
+					}
+				}
+			}
+		}
+
+		comp.into_iter().map(|c| counts[c]).collect()
+	}
+}
+
+/// Draws the beams in `states`, either as a map of the energized tiles marked with `#`,
+/// or over the contraption with the directions of the beams on empty tiles,
+/// like in the puzzle text.
+fn render_text(grid: &Grid, graph: &BeamGraph, states: &[usize], arrows: bool) -> String {
+	let mut beams = HashMap::<Point, Vec<Dir>>::new();
+	for &state in states {
+		beams
+			.entry(graph.pos(state))
+			.or_default()
+			.push(BeamGraph::dir(state));
 	}
 
-	cycles
-		.into_iter()
-		.map(|(_, pos)| pos)
-		.collect::<HashSet<_>>()
-		.len()
+	let mut res = String::new();
+	for y in 0..grid.height() {
+		for x in 0..grid.width() {
+			let tile = grid[(x, y)];
+			let c = match beams.get(&(x, y)) {
+				None if !arrows => '.',
+				None => tile as char,
+				Some(_) if !arrows => '#',
+				Some(_) if tile != b'.' => tile as char,
+				Some(dirs) if dirs.len() > 1 => char::from_digit(dirs.len() as u32, 10).unwrap(),
+				Some(dirs) => match dirs[0] {
+					(1, 0) => '>',
+					(-1, 0) => '<',
+					(0, 1) => 'v',
+					_ => '^',
+				},
+			};
+			res.push(c);
+		}
+		res.push('\n');
+	}
+	res
+}
+
+/// Binary PPM image of the contraption, one pixel per tile, with the energized tiles in yellow.
+fn render_ppm(grid: &Grid, graph: &BeamGraph, states: &[usize]) -> Vec<u8> {
+	let energized: HashSet<_> = states.iter().map(|&state| graph.pos(state)).collect();
+	let mut res = format!("P6\n{} {}\n255\n", grid.width(), grid.height()).into_bytes();
+	for y in 0..grid.height() {
+		for x in 0..grid.width() {
+			let color = match (energized.contains(&(x, y)), grid[(x, y)]) {
+				(true, b'.') => [255, 200, 0],
+				(true, _) => [255, 255, 255],
+				(false, b'.') => [0, 0, 0],
+				(false, _) => [128, 128, 128],
+			};
+			res.extend(color);
+		}
+	}
+	res
+}
+
+/// Writes the beam after every step of its propagation to files named after `path`,
+/// as text or PPM images depending on its extension.
+fn write_frames(
+	grid: &Grid,
+	graph: &BeamGraph,
+	start: usize,
+	path: &Path,
+	arrows: bool,
+) -> Result<()> {
+	let format = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+	let stem = path.with_extension("");
+	let mut states = Vec::new();
+	for (i, step) in graph.trace(start).into_iter().enumerate() {
+		states.extend(step);
+		let frame = match format {
+			"txt" => render_text(grid, graph, &states, arrows).into_bytes(),
+			"ppm" => render_ppm(grid, graph, &states),
+			_ => bail!("unknown frame format: {format}"),
+		};
+		let mut name = stem.clone().into_os_string();
+		name.push(format!("_{i:04}.{format}"));
+		std::fs::write(name, frame)?;
+	}
+	Ok(())
+}
+
+fn threads() -> Result<usize> {
+	match std::env::var("DAY16_THREADS") {
+		Ok(n) => Ok(n.parse()?),
+		Err(_) => Ok(1),
+	}
 }
 
 fn part1(input: &str) -> Result<usize> {
-	let grid = Grid::for_str(input).unwrap();
-	let res = laser(grid, (1, 0), (0, 0));
+	let grid = Grid::for_str(input).ok_or_else(|| eyre!("invalid grid"))?;
+	let graph = BeamGraph::new(&grid)?;
+	let start = graph.id((0, 0), (1, 0));
 
-	Ok(res)
+	let arrows = match std::env::var("DAY16_RENDER").as_deref() {
+		Ok("energized") | Err(_) => false,
+		Ok("arrows") => true,
+		Ok(style) => bail!("unknown render style: {style}"),
+	};
+	if std::env::var_os("DAY16_RENDER").is_some() {
+		let states: Vec<_> = graph.trace(start).into_iter().flatten().collect();
+		print!("{}", render_text(&grid, &graph, &states, arrows));
+	}
+	if let Some(path) = std::env::var_os("DAY16_FRAMES").map(PathBuf::from) {
+		write_frames(&grid, &graph, start, &path, arrows)?;
+	}
+
+	Ok(graph.energized_set(start).len())
 }
 
 fn part2(input: &str) -> Result<usize> {
-	let grid = Grid::for_str(input).unwrap();
+	let grid = Grid::for_str(input).ok_or_else(|| eyre!("invalid grid"))?;
+	let graph = BeamGraph::new(&grid)?;
+	let energized = graph.energized(threads()?);
 
 	let top = (0..grid.width()).map(|x| ((0, 1), (x, 0)));
 	let bottom = (0..grid.width()).map(|x| ((0, -1), (x, grid.height() - 1)));
 	let left = (0..grid.height()).map(|y| ((1, 0), (0, y)));
 	let right = (0..grid.height()).map(|y| ((-1, 0), (grid.width() - 1, y)));
 
-	let res = top
-		.chain(bottom)
+	top.chain(bottom)
 		.chain(left)
 		.chain(right)
-		.map(|(dir, start_pos)| laser(grid, dir, start_pos))
+		.map(|(dir, start_pos)| energized[graph.id(start_pos, dir)])
 		.max()
-		.unwrap();
-
-	Ok(res)
+		.ok_or_else(|| eyre!("empty grid"))
 }
 
 #[allow(dead_code)]
@@ -103,3 +430,96 @@ aoc! {
 	part1 => (EX_INPUT) 46,
 	part2 => (EX_INPUT) 51
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn render_example(arrows: bool) -> String {
+		let grid = Grid::for_str(EX_INPUT).unwrap();
+		let graph = BeamGraph::new(&grid).unwrap();
+		let states: Vec<_> = graph
+			.trace(graph.id((0, 0), (1, 0)))
+			.into_iter()
+			.flatten()
+			.collect();
+		render_text(&grid, &graph, &states, arrows)
+	}
+
+	#[test]
+	fn renders_energized_tiles() {
+		let expected = r#"
+######....
+.#...#....
+.#...#####
+.#...##...
+.#...##...
+.#...##...
+.#..####..
+########..
+.#######..
+.#...#.#..
+"#;
+		assert_eq!(render_example(false), expected.trim_start());
+	}
+
+	#[test]
+	fn renders_beam_directions() {
+		let expected = r#"
+>|<<<\....
+|v-.\^....
+.v...|->>>
+.v...v^.|.
+.v...v^...
+.v...v^..\
+.v../2\\..
+<->-/vv|..
+.|<<<2-|.\
+.v//.|.v..
+"#;
+		assert_eq!(render_example(true), expected.trim_start());
+	}
+
+	#[test]
+	fn renders_ppm_frames() {
+		let grid = Grid::for_str(EX_INPUT).unwrap();
+		let graph = BeamGraph::new(&grid).unwrap();
+		let start = graph.id((0, 0), (1, 0));
+		let image = render_ppm(&grid, &graph, &[start]);
+		let header = b"P6\n10 10\n255\n";
+		assert_eq!(&image[..header.len()], header);
+		assert_eq!(image.len(), header.len() + 10 * 10 * 3);
+		assert_eq!(
+			image[header.len()..header.len() + 6],
+			[255, 200, 0, 128, 128, 128]
+		);
+
+		let dir = std::env::temp_dir().join("day16_frames_test");
+		std::fs::create_dir_all(&dir).unwrap();
+		write_frames(&grid, &graph, start, &dir.join("beam.txt"), false).unwrap();
+		let frames = graph.trace(start).len();
+		let last =
+			std::fs::read_to_string(dir.join(format!("beam_{:04}.txt", frames - 1))).unwrap();
+		assert_eq!(last, render_example(false));
+		assert!(write_frames(&grid, &graph, start, &dir.join("beam.gif"), false).is_err());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn condensed_graph_agrees_with_tracing() {
+		for input in [EX_INPUT, INPUT] {
+			let grid = Grid::for_str(input).unwrap();
+			let graph = BeamGraph::new(&grid).unwrap();
+			let single = graph.energized(1);
+			assert_eq!(graph.energized(4), single);
+			for y in 0..grid.height() {
+				for x in [0, grid.width() - 1] {
+					for dir in DIRS {
+						let start = graph.id((x, y), dir);
+						assert_eq!(graph.energized_set(start).len(), single[start]);
+					}
+				}
+			}
+		}
+	}
+}
@@ -1,63 +1,216 @@
+use std::{
+	cmp::Reverse,
+	collections::{BinaryHeap, HashMap, HashSet},
+};
+
 use aoc_lib::{
 	aoc,
-	color_eyre::eyre::Result,
-	grid::{Grid, PointExt},
+	color_eyre::eyre::{bail, eyre, Result},
+	grid::{Grid, Point, PointExt},
 };
 
 static INPUT: &str = include_str!("../../inputs/day17");
 
-fn solve(input: &str, min_moves: i64, max_moves: i64) -> i64 {
-	let grid = Grid::for_str(input).unwrap();
+type Dir = Point;
+type State = (Point, Dir);
+
+struct Route {
+	cost: i64,
+	/// Direction of every single step from the start.
+	moves: Vec<Dir>,
+	/// Number of states taken off the queue before reaching the goal.
+	expanded: usize,
+}
 
-	let start = (0, 0);
-	let end = (grid.width() - 1, grid.height() - 1);
+/// How a crucible is allowed to move, and where it goes.
+struct Rules {
+	/// Shortest straight run before the crucible can turn.
+	min_run: i64,
+	/// Longest straight run before the crucible has to turn.
+	max_run: i64,
+	/// Whether the crucible can turn back the way it came instead of only turning 90 degrees.
+	allow_reverse: bool,
+	/// Whether the crucible has to complete the shortest run before stopping at a goal.
+	final_min: bool,
+	start: Point,
+	/// Cells any of which ends the route.
+	goals: Vec<Point>,
+}
 
-	let get_val = |pos| grid.get_pos(pos).map(|&v| (v - b'0') as i64);
+impl Rules {
+	/// Regular crucible, going from the top left to the bottom right corner.
+	fn crucible(grid: &Grid, min_run: i64, max_run: i64) -> Self {
+		Rules {
+			min_run,
+			max_run,
+			allow_reverse: false,
+			final_min: true,
+			start: (0, 0),
+			goals: vec![(grid.width() - 1, grid.height() - 1)],
+		}
+	}
 
-	let neighbors = |&((x, y), (dx, dy)): &_| {
-		let horizontal = (dx == 0)
-			.then(move || {
-				(min_moves..=max_moves)
-					.flat_map(move |dx| [((x + dx, y), (dx, 0)), ((x - dx, y), (-dx, 0))])
-			})
+	fn validate(&self, grid: &Grid) -> Result<()> {
+		if self.min_run < 1 || self.max_run < self.min_run {
+			bail!("invalid run lengths: {}..={}", self.min_run, self.max_run);
+		}
+		if let Some(pos) = std::iter::once(&self.start)
+			.chain(&self.goals)
+			.find(|&&pos| !grid.is_valid_pos(pos))
+		{
+			bail!("{pos:?} is outside of the grid");
+		}
+		if self.goals.is_empty() {
+			bail!("no goals to reach");
+		}
+		Ok(())
+	}
+
+	fn is_goal(&self, pos: Point) -> bool {
+		self.goals.contains(&pos)
+	}
+
+	/// States reachable from `(pos, dir)` in a single straight run, along with the heat lost on the way.
+	fn neighbors<'a>(
+		&'a self,
+		grid: &'a Grid,
+		&((x, y), dir): &State,
+	) -> impl Iterator<Item = (State, i64)> + 'a {
+		let get_val = |pos| grid.get_pos(pos).map(|&v| (v - b'0') as i64);
+		let shortest = if self.final_min { self.min_run } else { 1 };
+
+		[(1, 0), (-1, 0), (0, 1), (0, -1)]
 			.into_iter()
-			.flatten();
-		let vertical = (dy == 0)
-			.then(move || {
-				(min_moves..=max_moves)
-					.flat_map(move |dy| [((x, y + dy), (0, dy)), ((x, y - dy), (0, -dy))])
+			.filter(move |&d| d != dir && (self.allow_reverse || d != dir.map(|v| -v)))
+			.flat_map(move |d| (shortest..=self.max_run).map(move |len| (d, len)))
+			.filter_map(move |(diff, len)| {
+				let pos = (x + diff.0 * len, y + diff.1 * len);
+				if len < self.min_run && !self.is_goal(pos) {
+					return None;
+				}
+				let cost = (1..=len)
+					.map(|i| get_val((x + diff.0 * i, y + diff.1 * i)))
+					.try_fold(0, |acc, curr| Some(acc + curr?))?;
+				Some(((pos, diff), cost))
 			})
-			.into_iter()
-			.flatten();
+	}
+}
 
-		horizontal.chain(vertical).filter_map(move |(pos, d)| {
-			let diff = d.map(i64::signum);
-			let cost =
-				std::iter::successors(Some(diff), |prev| (*prev != d).then_some(diff.add(prev)))
-					.map(|d| get_val(d.add(&(x, y))))
-					.try_fold(0, |acc, curr| Some(acc + curr?))?;
-			Some(((pos, diff), cost))
-		})
+/// A* from the start to the closest goal. Without `heuristic` it's plain Dijkstra.
+///
+/// The heuristic is the Manhattan distance to the nearest goal times the cheapest block,
+/// so it never overestimates the remaining heat loss.
+fn search(grid: &Grid, rules: &Rules, heuristic: bool) -> Result<Route> {
+	rules.validate(grid)?;
+	let start = (rules.start, (0, 0));
+
+	let cheapest = grid
+		.iter_rows()
+		.flatten()
+		.map(|&v| (v - b'0') as i64)
+		.min()
+		.unwrap_or(0);
+	let estimate = |(x, y): Point| {
+		if heuristic {
+			let dist = rules
+				.goals
+				.iter()
+				.map(|goal| (goal.0 - x).abs() + (goal.1 - y).abs())
+				.min()
+				.unwrap_or(0);
+			cheapest * dist
+		} else {
+			0
+		}
 	};
 
-	let (dist, _) = aoc_lib::algo::dijkstra((start, (0, 0)), neighbors);
+	let mut best = HashMap::from([(start, 0)]);
+	let mut prev = HashMap::<State, State>::new();
+	let mut done = HashSet::new();
+	let mut queue = BinaryHeap::from([Reverse((estimate(start.0), 0, start))]);
+	let mut expanded = 0;
 
-	dist.iter()
-		.filter_map(|(&(p, _), &d)| (p == end).then_some(d))
-		.min()
-		.unwrap()
+	while let Some(Reverse((_, cost, state))) = queue.pop() {
+		if !done.insert(state) {
+			continue;
+		}
+		expanded += 1;
+
+		if rules.is_goal(state.0) {
+			let mut states = vec![state];
+			while let Some(&p) = prev.get(states.last().unwrap()) {
+				states.push(p);
+			}
+			let moves = states
+				.windows(2)
+				.rev()
+				.flat_map(|w| {
+					let ((to, dir), (from, _)) = (w[0], w[1]);
+					let len = (to.0 - from.0).abs() + (to.1 - from.1).abs();
+					(0..len).map(move |_| dir)
+				})
+				.collect();
+			return Ok(Route {
+				cost,
+				moves,
+				expanded,
+			});
+		}
+
+		for (next, step_cost) in rules.neighbors(grid, &state) {
+			let next_cost = cost + step_cost;
+			if !matches!(best.get(&next), Some(&c) if c <= next_cost) {
+				best.insert(next, next_cost);
+				prev.insert(next, state);
+				queue.push(Reverse((next_cost + estimate(next.0), next_cost, next)));
+			}
+		}
+	}
+
+	bail!("the goal can't be reached")
 }
 
-fn part1(input: &str) -> Result<i64> {
-	let res = solve(input, 1, 3);
+/// The grid with every step of the route drawn over it, like in the puzzle text.
+fn draw_route(grid: &Grid, start: Point, moves: &[Dir]) -> String {
+	let mut rows: Vec<Vec<u8>> = grid.iter_rows().map(|row| row.to_vec()).collect();
+	let mut pos = start;
+	for dir in moves {
+		pos = pos.add(dir);
+		rows[pos.1 as usize][pos.0 as usize] = match dir {
+			(1, 0) => b'>',
+			(-1, 0) => b'<',
+			(0, 1) => b'v',
+			_ => b'^',
+		};
+	}
+	rows.into_iter()
+		.map(|row| String::from_utf8_lossy(&row).into_owned() + "\n")
+		.collect()
+}
 
-	Ok(res)
+fn solve(input: &str, min_run: i64, max_run: i64) -> Result<i64> {
+	let grid = Grid::for_str(input).ok_or_else(|| eyre!("invalid grid"))?;
+	let rules = Rules::crucible(&grid, min_run, max_run);
+	let route = search(&grid, &rules, true)?;
+
+	if std::env::var_os("DAY17_ROUTE").is_some() {
+		print!("{}", draw_route(&grid, rules.start, &route.moves));
+		let dijkstra = search(&grid, &rules, false)?;
+		println!(
+			"A* expanded {} states, Dijkstra expanded {}",
+			route.expanded, dijkstra.expanded
+		);
+	}
+
+	Ok(route.cost)
 }
 
-fn part2(input: &str) -> Result<i64> {
-	let res = solve(input, 4, 10);
+fn part1(input: &str) -> Result<i64> {
+	solve(input, 1, 3)
+}
 
-	Ok(res)
+fn part2(input: &str) -> Result<i64> {
+	solve(input, 4, 10)
 }
 
 #[allow(dead_code)]
@@ -82,3 +235,126 @@ aoc! {
 	part1 => (EX_INPUT) 102,
 	part2 => (EX_INPUT) 94
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn route(input: &str, rules: &Rules) -> Result<Route> {
+		let grid = Grid::for_str(input).unwrap();
+		let route = search(&grid, rules, true)?;
+
+		// the route has to add up, end in a goal and agree with Dijkstra
+		let mut pos = rules.start;
+		let mut cost = 0;
+		for dir in &route.moves {
+			pos = pos.add(dir);
+			cost += (grid[pos] - b'0') as i64;
+		}
+		assert_eq!(cost, route.cost);
+		assert!(rules.is_goal(pos));
+		assert_eq!(search(&grid, rules, false)?.cost, route.cost);
+
+		Ok(route)
+	}
+
+	fn line_rules(min_run: i64, max_run: i64) -> Rules {
+		Rules {
+			min_run,
+			max_run,
+			allow_reverse: false,
+			final_min: true,
+			start: (0, 0),
+			goals: vec![(1, 0)],
+		}
+	}
+
+	#[test]
+	fn reversing() {
+		let rules = line_rules(2, 3);
+		assert!(route("11111", &rules).is_err());
+
+		let rules = Rules {
+			allow_reverse: true,
+			..rules
+		};
+		let route = route("11111", &rules).unwrap();
+		assert_eq!(route.cost, 5);
+		assert_eq!(route.moves, [(1, 0), (1, 0), (1, 0), (-1, 0), (-1, 0)]);
+	}
+
+	#[test]
+	fn short_final_run() {
+		let rules = Rules {
+			final_min: false,
+			..line_rules(2, 3)
+		};
+		let route = route("11111", &rules).unwrap();
+		assert_eq!(route.cost, 1);
+		assert_eq!(route.moves, [(1, 0)]);
+
+		let grid = Grid::for_str(EX_INPUT).unwrap();
+		let rules = Rules {
+			final_min: false,
+			..Rules::crucible(&grid, 4, 10)
+		};
+		assert!(self::route(EX_INPUT, &rules).unwrap().cost <= 94);
+	}
+
+	#[test]
+	fn any_start_and_goals() {
+		let grid = Grid::for_str(EX_INPUT).unwrap();
+		let corners = [(0, 12), (12, 0), (12, 12)];
+		let single: Vec<_> = corners
+			.iter()
+			.map(|&goal| {
+				let rules = Rules {
+					start: (6, 6),
+					goals: vec![goal],
+					..Rules::crucible(&grid, 1, 3)
+				};
+				route(EX_INPUT, &rules).unwrap().cost
+			})
+			.collect();
+
+		let rules = Rules {
+			start: (6, 6),
+			goals: corners.to_vec(),
+			..Rules::crucible(&grid, 1, 3)
+		};
+		assert_eq!(
+			route(EX_INPUT, &rules).unwrap().cost,
+			single.into_iter().min().unwrap()
+		);
+	}
+
+	#[test]
+	fn invalid_rules() {
+		let grid = Grid::for_str(EX_INPUT).unwrap();
+		let crucible = || Rules::crucible(&grid, 1, 3);
+		for rules in [
+			Rules {
+				min_run: 4,
+				..crucible()
+			},
+			Rules {
+				min_run: 0,
+				..crucible()
+			},
+			Rules {
+				goals: vec![],
+				..crucible()
+			},
+			Rules {
+				goals: vec![(13, 0)],
+				..crucible()
+			},
+			Rules {
+				start: (-1, 0),
+				..crucible()
+			},
+		] {
+			assert!(search(&grid, &rules, true).is_err());
+		}
+	}
+}
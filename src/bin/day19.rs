@@ -1,30 +1,25 @@
 use std::{
-	collections::{HashMap, VecDeque},
-	str::FromStr,
+	collections::{HashMap, HashSet, VecDeque},
+	fmt,
+	path::PathBuf,
 };
 
-use aoc_lib::{aoc, color_eyre::eyre::Result, to_lines};
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, eyre, Result},
+	to_lines,
+};
 
 static INPUT: &str = include_str!("../../inputs/day19");
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Res {
 	Accept,
 	Reject,
-	Send(String),
+	Send(usize),
 }
 
-impl Res {
-	fn for_str(s: &str) -> Res {
-		match s {
-			"A" => Res::Accept,
-			"R" => Res::Reject,
-			s => Res::Send(s.to_string()),
-		}
-	}
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct ValRange {
 	gt: u64,
 	lt: u64,
@@ -51,7 +46,7 @@ impl ValRange {
 	fn combine(&self, other: &Self) -> Option<ValRange> {
 		let gt = self.gt.max(other.gt);
 		let lt = self.lt.min(other.lt);
-		if gt >= lt {
+		if gt + 1 >= lt {
 			None
 		} else {
 			Some(ValRange { gt, lt })
@@ -67,11 +62,21 @@ impl ValRange {
 		self.gt + 1
 	}
 
-	fn reverse(&self) -> Self {
-		let d = Self::default();
-		let gt = if self.lt == d.lt { d.gt } else { self.lt - 1 };
-		let lt = if self.gt == d.gt { d.lt } else { self.gt + 1 };
-		ValRange { gt, lt }
+	/// Join two ranges which are directly next to each other.
+	fn join(&self, other: &Self) -> Option<ValRange> {
+		if self.lt == other.gt + 1 || other.lt == self.gt + 1 {
+			Some(ValRange {
+				gt: self.gt.min(other.gt),
+				lt: self.lt.max(other.lt),
+			})
+		} else {
+			None
+		}
+	}
+
+	#[cfg(test)]
+	fn contains(&self, other: &Self) -> bool {
+		self.gt <= other.gt && self.lt >= other.lt
 	}
 }
 
@@ -84,210 +89,678 @@ impl Default for ValRange {
 	}
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// A hyper-rectangle of ratings, indexed by attribute id.
+/// Attributes past the end of `props` are unconstrained.
+#[derive(Debug, Clone, Default)]
 struct Obj {
-	x: ValRange,
-	m: ValRange,
-	a: ValRange,
-	s: ValRange,
+	props: Vec<ValRange>,
 }
 
 impl Obj {
-	fn combine_prop(self, prop: u8, range: ValRange) -> Option<Obj> {
-		Some(match prop {
-			b'x' => Obj {
-				x: self.x.combine(&range)?,
-				..self
-			},
-			b'm' => Obj {
-				m: self.m.combine(&range)?,
-				..self
-			},
-			b'a' => Obj {
-				a: self.a.combine(&range)?,
-				..self
-			},
-			b's' => Obj {
-				s: self.s.combine(&range)?,
-				..self
-			},
-			_ => unreachable!(),
+	fn prop(&self, prop: usize) -> ValRange {
+		self.props.get(prop).copied().unwrap_or_default()
+	}
+
+	fn combine_prop(&self, prop: usize, range: ValRange) -> Option<Obj> {
+		let mut res = self.clone();
+		if res.props.len() <= prop {
+			res.props.resize(prop + 1, ValRange::default());
+		}
+		res.props[prop] = res.props[prop].combine(&range)?;
+		Some(res)
+	}
+
+	/// Number of parts in the box, `None` if it doesn't fit in a `u128`.
+	fn count(&self, attr_cnt: usize) -> Option<u128> {
+		(0..attr_cnt).try_fold(1u128, |acc, prop| {
+			acc.checked_mul(self.prop(prop).count() as u128)
 		})
 	}
+
+	fn rating(&self) -> u64 {
+		self.props
+			.iter()
+			.filter(|range| range.count() == 1)
+			.map(ValRange::value)
+			.sum()
+	}
+
+	#[cfg(test)]
+	fn contains(&self, other: &Obj) -> bool {
+		(0..self.props.len().max(other.props.len()))
+			.all(|prop| self.prop(prop).contains(&other.prop(prop)))
+	}
+
+	/// Join two boxes which are equal in every attribute
+	/// except for one, along which they are next to each other.
+	fn join(&self, other: &Obj) -> Option<Obj> {
+		let attr_cnt = self.props.len().max(other.props.len());
+		let mut differing = (0..attr_cnt).filter(|&prop| self.prop(prop) != other.prop(prop));
+		let prop = differing.next()?;
+		if differing.next().is_some() {
+			return None;
+		}
+		let range = self.prop(prop).join(&other.prop(prop))?;
+		let mut res = self.clone();
+		res.props.resize(attr_cnt, ValRange::default());
+		res.props[prop] = range;
+		Some(res)
+	}
 }
 
-impl FromStr for Obj {
-	type Err = ();
+fn merge_boxes(mut boxes: Vec<Obj>) -> Vec<Obj> {
+	let mut i = 0;
+	while i < boxes.len() {
+		let joined =
+			(i + 1..boxes.len()).find_map(|j| boxes[i].join(&boxes[j]).map(|joined| (j, joined)));
+		if let Some((j, joined)) = joined {
+			boxes.swap_remove(j);
+			boxes[i] = joined;
+			// the joined box might now fit with one we've already moved past
+			i = 0;
+		} else {
+			i += 1;
+		}
+	}
+	boxes
+}
 
-	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-		let mut res = Obj::default();
-		let line = s.trim();
+/// Comparison of a single attribute, `prop < val` or `prop > val`.
+#[derive(Debug, Clone, Copy)]
+struct Test {
+	prop: usize,
+	op: u8,
+	val: u64,
+}
 
-		for prop in line[1..line.len() - 1].split(',') {
-			let (name, val) = prop.split_once('=').unwrap();
-			let name = name.as_bytes()[0];
-			let val: u64 = val.parse().unwrap();
-			let range = ValRange {
-				gt: val - 1,
-				lt: val + 1,
-			};
-			res = res.combine_prop(name, range).unwrap();
+impl Test {
+	/// Ratings passing the test.
+	fn range(&self) -> ValRange {
+		if self.op == b'<' {
+			ValRange::new_less_than(self.val)
+		} else {
+			ValRange::new_greater_than(self.val)
 		}
+	}
 
-		Ok(res)
+	/// Ratings failing the test, `None` if every rating passes it.
+	fn reverse(&self) -> Option<ValRange> {
+		let (min, max) = (ValRange::MIN, ValRange::MAX);
+		match self.op {
+			b'<' if self.val > max => None,
+			b'<' if self.val <= min => Some(ValRange::default()),
+			b'<' => Some(ValRange::new_greater_than(self.val - 1)),
+			_ if self.val < min => None,
+			_ if self.val >= max => Some(ValRange::default()),
+			_ => Some(ValRange::new_less_than(self.val + 1)),
+		}
 	}
 }
 
-type RangeWithTarget = (Option<(u8, ValRange)>, Res);
-fn parse_ins(line: &str) -> (String, Vec<RangeWithTarget>) {
-	let line = line.trim();
-	let idx = line.find('{').unwrap();
-	let name = &line[..idx];
-	let ins = &line[idx + 1..line.len() - 1];
-
-	let ins: Vec<_> = ins
-		.split(',')
-		.map(|instr| {
-			if let Some(idx) = instr.find(':') {
-				let res = Res::for_str(&instr[idx + 1..]);
-				let cmd = &instr[..idx];
-
-				let f = if let Some((prop, val)) = cmd.split_once('>') {
-					let prop = prop.as_bytes()[0];
-					let val = val.parse().unwrap();
-					(prop, ValRange::new_greater_than(val))
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+	test: Option<Test>,
+	target: Res,
+}
+
+/// Workflows compiled into a decision graph.
+/// Workflow names and attribute names are interned, so the graph is walked by index.
+#[derive(Debug, Default)]
+struct Workflows {
+	names: Vec<String>,
+	ids: HashMap<String, usize>,
+	attrs: Vec<String>,
+	rules: Vec<Vec<Rule>>,
+}
+
+impl Workflows {
+	const START: &'static str = "in";
+
+	/// Parse the workflows along with the ratings below them, if there are any.
+	/// Attributes are numbered in the order the ratings list them,
+	/// so a part has exactly the attributes it is rated in.
+	fn parse(input: &str) -> (Workflows, Vec<Obj>) {
+		let (ins, parts) = input.trim().split_once("\n\n").unwrap_or((input, ""));
+
+		let mut res = Workflows::default();
+		res.workflow_id(Workflows::START);
+		let objs = to_lines(parts).map(|line| res.parse_obj(line)).collect();
+		to_lines(ins).for_each(|line| res.add_workflow(line));
+		(res, objs)
+	}
+
+	fn workflow_id(&mut self, name: &str) -> usize {
+		if let Some(&id) = self.ids.get(name) {
+			return id;
+		}
+		let id = self.names.len();
+		self.names.push(name.to_string());
+		self.ids.insert(name.to_string(), id);
+		self.rules.push(Vec::new());
+		id
+	}
+
+	fn attr_id(&mut self, name: &str) -> usize {
+		self.attrs
+			.iter()
+			.position(|attr| attr == name)
+			.unwrap_or_else(|| {
+				self.attrs.push(name.to_string());
+				self.attrs.len() - 1
+			})
+	}
+
+	fn target(&mut self, s: &str) -> Res {
+		match s {
+			"A" => Res::Accept,
+			"R" => Res::Reject,
+			s => Res::Send(self.workflow_id(s)),
+		}
+	}
+
+	fn add_workflow(&mut self, line: &str) {
+		let line = line.trim();
+		let idx = line.find('{').unwrap();
+		let id = self.workflow_id(&line[..idx]);
+
+		let rules = line[idx + 1..line.len() - 1]
+			.split(',')
+			.map(|instr| {
+				let Some((cmd, target)) = instr.split_once(':') else {
+					return Rule {
+						test: None,
+						target: self.target(instr),
+					};
+				};
+
+				let (prop, op, val) = if let Some((prop, val)) = cmd.split_once('>') {
+					(prop, b'>', val)
 				} else if let Some((prop, val)) = cmd.split_once('<') {
-					let prop = prop.as_bytes()[0];
-					let val = val.parse().unwrap();
-					(prop, ValRange::new_less_than(val))
+					(prop, b'<', val)
 				} else {
 					unreachable!()
 				};
-				(Some(f), res)
-			} else {
-				let res = Res::for_str(instr);
-				(None, res)
-			}
-		})
-		.collect();
 
-	(name.to_string(), ins)
-}
-
-fn part1(input: &str) -> Result<u64> {
-	let (ins, objs) = {
-		let mut i = input.trim().split("\n\n");
-		(i.next().unwrap(), i.next().unwrap())
-	};
-
-	let ins: HashMap<_, _> = to_lines(ins).map(parse_ins).collect();
-
-	let res = to_lines(objs)
-		.map(Obj::from_str)
-		.filter_map(|obj| {
-			let obj = obj.unwrap();
-			let mut curr = "in";
-
-			'outer: loop {
-				let tests = ins.get(curr).unwrap();
-
-				for (test, target) in tests {
-					if test
-						.map(|(prop, range)| obj.combine_prop(prop, range).is_none())
-						.unwrap_or(false)
-					{
-						continue;
-					}
-					match target {
-						Res::Send(next) => {
-							curr = next;
-							continue 'outer;
-						}
-						Res::Accept => {
-							return Some(
-								obj.x.value() + obj.m.value() + obj.a.value() + obj.s.value(),
-							)
-						}
-						Res::Reject => return None,
-					}
+				Rule {
+					test: Some(Test {
+						prop: self.attr_id(prop),
+						op,
+						val: val.parse().unwrap(),
+					}),
+					target: self.target(target),
 				}
-				unreachable!()
+			})
+			.collect();
+
+		self.rules[id] = rules;
+	}
+
+	fn parse_obj(&mut self, line: &str) -> Obj {
+		let line = line.trim();
+
+		line[1..line.len() - 1]
+			.split(',')
+			.fold(Obj::default(), |obj, prop| {
+				let (name, val) = prop.split_once('=').unwrap();
+				let val: u64 = val.parse().unwrap();
+				let range = ValRange {
+					gt: val - 1,
+					lt: val + 1,
+				};
+				obj.combine_prop(self.attr_id(name), range).unwrap()
+			})
+	}
+
+	fn start(&self) -> usize {
+		*self.ids.get(Self::START).unwrap()
+	}
+
+	fn export(&self, boxes: &[Obj], format: &str) -> Result<String> {
+		let bounds = |obj: &Obj, prop| {
+			let range = obj.prop(prop);
+			(range.gt + 1, range.lt - 1)
+		};
+
+		let res = match format {
+			"csv" => {
+				let header = self
+					.attrs
+					.iter()
+					.map(|attr| format!("{attr}_min,{attr}_max"))
+					.collect::<Vec<_>>()
+					.join(",");
+				let rows = boxes.iter().map(|obj| {
+					(0..self.attrs.len())
+						.map(|prop| {
+							let (min, max) = bounds(obj, prop);
+							format!("{min},{max}")
+						})
+						.collect::<Vec<_>>()
+						.join(",")
+				});
+				std::iter::once(header)
+					.chain(rows)
+					.map(|line| line + "\n")
+					.collect()
 			}
-		})
-		.sum();
+			"json" => {
+				let objs = boxes
+					.iter()
+					.map(|obj| {
+						let props = (0..self.attrs.len())
+							.map(|prop| {
+								let (min, max) = bounds(obj, prop);
+								format!("\"{}\":[{min},{max}]", self.attrs[prop])
+							})
+							.collect::<Vec<_>>()
+							.join(",");
+						format!("{{{props}}}")
+					})
+					.collect::<Vec<_>>()
+					.join(",\n");
+				format!("[\n{objs}\n]\n")
+			}
+			format => bail!("unknown export format: {format}"),
+		};
 
-	Ok(res)
-}
+		Ok(res)
+	}
 
-fn part2(input: &str) -> Result<u64> {
-	let ins = input.trim().split("\n\n").next().unwrap();
-	let ins: HashMap<_, _> = to_lines(ins)
-		.map(|line| {
-			let line = line.trim();
-			let idx = line.find('{').unwrap();
-			let name = &line[..idx];
-			let ins = &line[idx + 1..line.len() - 1];
-
-			let ins: Vec<_> = ins
-				.split(',')
-				.map(|instr| {
-					if let Some(idx) = instr.find(':') {
-						let res = Res::for_str(&instr[idx + 1..]);
-						let cmd = &instr[..idx];
-
-						let f = if let Some((prop, val)) = cmd.split_once('>') {
-							let prop = prop.as_bytes()[0];
-							let val = val.parse().unwrap();
-							(prop, ValRange::new_greater_than(val))
-						} else if let Some((prop, val)) = cmd.split_once('<') {
-							let prop = prop.as_bytes()[0];
-							let val = val.parse().unwrap();
-							(prop, ValRange::new_less_than(val))
-						} else {
-							unreachable!()
-						};
-						(Some(f), res)
-					} else {
-						let res = Res::for_str(instr);
-						(None, res)
-					}
-				})
-				.collect();
+	fn describe_rule(&self, rule: &Rule) -> String {
+		let target = match rule.target {
+			Res::Accept => "A",
+			Res::Reject => "R",
+			Res::Send(next) => &self.names[next],
+		};
+		match rule.test {
+			Some(test) => format!(
+				"{}{}{}:{target}",
+				self.attrs[test.prop], test.op as char, test.val
+			),
+			None => target.to_string(),
+		}
+	}
 
-			(name.to_string(), ins)
+	fn targets(&self, id: usize) -> impl Iterator<Item = usize> + '_ {
+		self.rules[id].iter().filter_map(|rule| match rule.target {
+			Res::Send(next) => Some(next),
+			_ => None,
 		})
-		.collect();
+	}
 
-	let mut queue = VecDeque::new();
-	queue.push_back(("in", Obj::default()));
+	/// Ratings in a box, like `{x=1..1999,m=1..4000}`.
+	fn describe_obj(&self, obj: &Obj) -> String {
+		let props = (0..self.attrs.len())
+			.map(|prop| {
+				let range = obj.prop(prop);
+				format!("{}={}..{}", self.attrs[prop], range.gt + 1, range.lt - 1)
+			})
+			.collect::<Vec<_>>()
+			.join(",");
+		format!("{{{props}}}")
+	}
 
-	let mut res = 0;
-	'outer: while let Some((curr_ins, mut obj)) = queue.pop_front() {
-		for (ins, target) in ins.get(curr_ins).unwrap() {
-			let new_obj = if let Some((prop, range)) = ins {
-				let Some(new_obj) = obj.combine_prop(*prop, *range) else {
-					continue 'outer;
+	/// Split a box of ratings along the rules of a workflow,
+	/// into the ratings every rule matches and the ones falling through all of them.
+	fn split(&self, id: usize, obj: Obj) -> (Vec<Option<Obj>>, Option<Obj>) {
+		let mut rest = Some(obj);
+		let matched = self.rules[id]
+			.iter()
+			.map(|rule| {
+				let obj = rest.take()?;
+				let Some(test) = rule.test else {
+					return Some(obj);
 				};
-				let Some(next_obj) = obj.combine_prop(*prop, range.reverse()) else {
-					continue 'outer;
-				};
-				obj = next_obj;
-				new_obj
-			} else {
-				obj
+				rest = test
+					.reverse()
+					.and_then(|range| obj.combine_prop(test.prop, range));
+				obj.combine_prop(test.prop, test.range())
+			})
+			.collect();
+		(matched, rest)
+	}
+
+	/// Push a box of ratings along every rule it can match, starting at the end of `path`,
+	/// collecting the problems a part from the box runs into.
+	fn check_paths(&self, path: &mut Vec<usize>, obj: Obj, errors: &mut Vec<String>) {
+		let curr = *path.last().unwrap();
+		let (matched, rest) = self.split(curr, obj);
+		if let Some(rest) = rest {
+			errors.push(format!(
+				"ratings {} fall through every rule of {}",
+				self.describe_obj(&rest),
+				self.names[curr]
+			));
+		}
+
+		for (rule, matched) in self.rules[curr].iter().zip(matched) {
+			let (Some(matched), Res::Send(next)) = (matched, rule.target) else {
+				continue;
 			};
+			if self.rules[next].is_empty() {
+				errors.push(format!("undefined workflow: {}", self.names[next]));
+			} else if let Some(idx) = path.iter().position(|&id| id == next) {
+				// the box only ever shrinks, so every part left in it goes around forever
+				let cycle: Vec<_> = path[idx..]
+					.iter()
+					.chain([&next])
+					.map(|&id| self.names[id].as_str())
+					.collect();
+				errors.push(format!(
+					"cycle: {} for ratings {}",
+					cycle.join(" -> "),
+					self.describe_obj(&matched)
+				));
+			} else {
+				path.push(next);
+				self.check_paths(path, matched, errors);
+				path.pop();
+			}
+		}
+	}
+
+	fn analyze(&self) -> Analysis {
+		let name = |id: usize| self.names[id].clone();
+		let splits: Vec<_> = (0..self.names.len())
+			.map(|id| self.split(id, Obj::default()))
+			.collect();
+
+		let edges: Vec<Vec<_>> = (0..self.names.len())
+			.map(|id| self.targets(id).collect())
+			.collect();
+
+		let mut reachable = vec![false; self.names.len()];
+		let mut stack = vec![self.start()];
+		while let Some(id) = stack.pop() {
+			if !reachable[id] {
+				reachable[id] = true;
+				stack.extend(&edges[id]);
+			}
+		}
+
+		// every defined workflow has at least one rule,
+		// so an empty one is a name that was only ever used as a target
+		let is_undefined = |id: usize| self.rules[id].is_empty();
+		let undefined = (0..self.names.len())
+			.filter(|&id| is_undefined(id))
+			.map(name)
+			.collect();
+
+		let unreachable = (0..self.names.len())
+			.filter(|&id| !reachable[id] && !is_undefined(id))
+			.map(name)
+			.collect();
+
+		fn dfs(
+			curr: usize,
+			edges: &[Vec<usize>],
+			on_path: &mut Vec<usize>,
+			done: &mut [bool],
+			cycles: &mut Vec<Vec<usize>>,
+		) {
+			if let Some(idx) = on_path.iter().position(|&id| id == curr) {
+				let mut cycle = on_path[idx..].to_vec();
+				cycle.push(curr);
+				cycles.push(cycle);
+				return;
+			}
+			if done[curr] {
+				return;
+			}
+
+			on_path.push(curr);
+			for &next in &edges[curr] {
+				dfs(next, edges, on_path, done, cycles);
+			}
+			on_path.pop();
+			done[curr] = true;
+		}
+
+		let mut cycles = Vec::new();
+		let mut done = vec![false; self.names.len()];
+		for id in 0..self.names.len() {
+			dfs(id, &edges, &mut Vec::new(), &mut done, &mut cycles);
+		}
+		let cycles = cycles
+			.into_iter()
+			.map(|cycle| cycle.into_iter().map(name).collect())
+			.collect();
+
+		let dead_rules = self
+			.rules
+			.iter()
+			.zip(&splits)
+			.enumerate()
+			.flat_map(|(id, (rules, (matched, _)))| {
+				rules
+					.iter()
+					.zip(matched)
+					.filter(|(_, matched)| matched.is_none())
+					.map(move |(rule, _)| (name(id), self.describe_rule(rule)))
+			})
+			.collect();
+
+		let unmatched = splits
+			.iter()
+			.enumerate()
+			.filter(|&(id, _)| !is_undefined(id))
+			.filter_map(|(id, (_, rest))| Some((name(id), self.describe_obj(rest.as_ref()?))))
+			.collect();
+
+		// Only what a part can actually run into stops the evaluation,
+		// so follow the ratings themselves rather than the rules.
+		let mut errors = Vec::new();
+		if is_undefined(self.start()) {
+			errors.push(format!("undefined workflow: {}", Self::START));
+		} else {
+			self.check_paths(&mut vec![self.start()], Obj::default(), &mut errors);
+		}
+		let mut seen = HashSet::new();
+		errors.retain(|error| seen.insert(error.clone()));
+
+		Analysis {
+			undefined,
+			unreachable,
+			cycles,
+			dead_rules,
+			unmatched,
+			errors,
+		}
+	}
+
+	fn is_accepted(&self, obj: &Obj) -> bool {
+		self.trace(obj).accepted
+	}
+
+	/// Run a part through the workflows,
+	/// remembering which rule matched in every visited workflow.
+	fn trace(&self, obj: &Obj) -> Trace {
+		let mut curr = self.start();
+		let mut steps = Vec::new();
+
+		'outer: loop {
+			for (idx, rule) in self.rules[curr].iter().enumerate() {
+				if rule
+					.test
+					.map(|test| obj.combine_prop(test.prop, test.range()).is_none())
+					.unwrap_or(false)
+				{
+					continue;
+				}
+				steps.push((curr, idx));
+				let accepted = match rule.target {
+					Res::Send(next) => {
+						curr = next;
+						continue 'outer;
+					}
+					Res::Accept => true,
+					Res::Reject => false,
+				};
+				return Trace { steps, accepted };
+			}
+			unreachable!("analyze rejects workflows a part can fall through")
+		}
+	}
+
+	fn format_trace(&self, line: &str, trace: &Trace, format: &str) -> Result<String> {
+		let result = if trace.accepted { "Accept" } else { "Reject" };
+		let steps = trace
+			.steps
+			.iter()
+			.map(|&(id, idx)| (&self.names[id], self.describe_rule(&self.rules[id][idx])));
+
+		let res = match format {
+			"txt" => {
+				let steps: String = steps
+					.map(|(name, rule)| format!("{name} ({rule}) -> "))
+					.collect();
+				format!("{line}: {steps}{result}")
+			}
+			"json" => {
+				let steps = steps
+					.map(|(name, rule)| format!("{{\"workflow\":\"{name}\",\"rule\":\"{rule}\"}}"))
+					.collect::<Vec<_>>()
+					.join(",");
+				format!("{{\"part\":\"{line}\",\"steps\":[{steps}],\"result\":\"{result}\"}}")
+			}
+			format => bail!("unknown trace format: {format}"),
+		};
 
-			match target {
-				Res::Accept => {
-					res += new_obj.x.count()
-						* new_obj.m.count() * new_obj.a.count()
-						* new_obj.s.count()
+		Ok(res)
+	}
+
+	/// Split the whole rating space along the rules,
+	/// returning the disjoint boxes which end up accepted.
+	fn accepted(&self) -> Vec<Obj> {
+		let mut queue = VecDeque::new();
+		queue.push_back((self.start(), Obj::default()));
+
+		let mut res = Vec::new();
+		while let Some((curr, obj)) = queue.pop_front() {
+			let (matched, _) = self.split(curr, obj);
+			for (rule, matched) in self.rules[curr].iter().zip(matched) {
+				match (matched, rule.target) {
+					(Some(matched), Res::Accept) => res.push(matched),
+					(Some(matched), Res::Send(next)) => queue.push_back((next, matched)),
+					_ => (),
 				}
-				Res::Reject => continue,
-				Res::Send(next) => queue.push_back((next.as_str(), new_obj)),
 			}
 		}
+
+		res
 	}
+}
+
+#[derive(Debug)]
+struct Trace {
+	/// (workflow, rule) pairs
+	steps: Vec<(usize, usize)>,
+	accepted: bool,
+}
+
+#[derive(Debug, Default)]
+struct Analysis {
+	undefined: Vec<String>,
+	unreachable: Vec<String>,
+	cycles: Vec<Vec<String>>,
+	/// (workflow, rule) pairs
+	dead_rules: Vec<(String, String)>,
+	/// (workflow, ratings) pairs of ratings no rule of the workflow matches
+	unmatched: Vec<(String, String)>,
+	/// Undefined targets, cycles and unmatched ratings a part can run into,
+	/// which make evaluation impossible. Everything else is merely suspicious.
+	errors: Vec<String>,
+}
+
+impl Analysis {
+	fn is_valid(&self) -> bool {
+		self.errors.is_empty()
+	}
+}
+
+impl fmt::Display for Analysis {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for name in &self.undefined {
+			writeln!(f, "undefined workflow: {name}")?;
+		}
+		for name in &self.unreachable {
+			writeln!(f, "unreachable workflow: {name}")?;
+		}
+		for cycle in &self.cycles {
+			writeln!(f, "cycle: {}", cycle.join(" -> "))?;
+		}
+		for (name, rule) in &self.dead_rules {
+			writeln!(f, "dead rule in {name}: {rule}")?;
+		}
+		for (name, ratings) in &self.unmatched {
+			writeln!(f, "unmatched ratings in {name}: {ratings}")?;
+		}
+		Ok(())
+	}
+}
+
+fn part1(input: &str) -> Result<u64> {
+	let (ins, objs) = Workflows::parse(input);
+	let analysis = ins.analyze();
+	if std::env::var_os("DAY19_ANALYZE").is_some() {
+		print!("{analysis}");
+	}
+	if !analysis.is_valid() {
+		bail!("invalid workflows:\n{}", analysis.errors.join("\n"));
+	}
+
+	if let Some(path) = std::env::var_os("DAY19_TRACE").map(PathBuf::from) {
+		let format = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+		let parts = input
+			.trim()
+			.split_once("\n\n")
+			.map_or("", |(_, parts)| parts);
+		let traces = to_lines(parts)
+			.zip(&objs)
+			.map(|(line, obj)| ins.format_trace(line, &ins.trace(obj), format))
+			.collect::<Result<Vec<_>>>()?;
+		let contents = if format == "json" {
+			format!("[\n{}\n]\n", traces.join(",\n"))
+		} else {
+			traces.iter().map(|trace| format!("{trace}\n")).collect()
+		};
+		std::fs::write(&path, contents)?;
+	}
+
+	let res = objs
+		.iter()
+		.filter(|obj| ins.is_accepted(obj))
+		.map(Obj::rating)
+		.sum();
+
+	Ok(res)
+}
+
+fn part2(input: &str) -> Result<u128> {
+	let (ins, _) = Workflows::parse(input);
+	let analysis = ins.analyze();
+	if !analysis.is_valid() {
+		bail!("invalid workflows:\n{}", analysis.errors.join("\n"));
+	}
+
+	let boxes = ins.accepted();
+
+	if let Some(path) = std::env::var_os("DAY19_EXPORT").map(PathBuf::from) {
+		let format = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+		let boxes = if std::env::var_os("DAY19_MERGE").is_some() {
+			merge_boxes(boxes.clone())
+		} else {
+			boxes.clone()
+		};
+		std::fs::write(&path, ins.export(&boxes, format)?)?;
+	}
+
+	let res = boxes
+		.iter()
+		.try_fold(0u128, |acc, obj| {
+			acc.checked_add(obj.count(ins.attrs.len())?)
+		})
+		.ok_or_else(|| eyre!("too many combinations for {} attributes", ins.attrs.len()))?;
 
 	Ok(res)
 }
@@ -318,3 +791,179 @@ aoc! {
 	part1 => (EX_INPUT) 19114,
 	part2 => (EX_INPUT) 167409079868000
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn region_agrees_with_parts() {
+		let (ins, objs) = Workflows::parse(EX_INPUT);
+		let region = ins.accepted();
+		for obj in &objs {
+			let in_region = region.iter().any(|accepted| accepted.contains(obj));
+			assert_eq!(in_region, ins.is_accepted(obj), "{obj:?}");
+		}
+	}
+
+	#[test]
+	fn merged_region_keeps_its_size() {
+		let (ins, _) = Workflows::parse(EX_INPUT);
+		let boxes = ins.accepted();
+		let merged = merge_boxes(boxes.clone());
+		assert!(merged.len() < boxes.len());
+		let size = |boxes: &[Obj]| {
+			boxes
+				.iter()
+				.map(|obj| obj.count(ins.attrs.len()).unwrap())
+				.sum::<u128>()
+		};
+		assert_eq!(size(&merged), size(&boxes));
+		for obj in &boxes {
+			assert!(merged.iter().any(|merged| merged.contains(obj)));
+		}
+	}
+
+	#[test]
+	fn export_formats() {
+		let (ins, _) = Workflows::parse("in{x<2000:A,R}\n\n{x=1,m=2,a=3,s=4}");
+		let boxes = ins.accepted();
+		assert_eq!(
+			ins.export(&boxes, "csv").unwrap(),
+			"x_min,x_max,m_min,m_max,a_min,a_max,s_min,s_max\n1,1999,1,4000,1,4000,1,4000\n"
+		);
+		assert_eq!(
+			ins.export(&boxes, "json").unwrap(),
+			"[\n{\"x\":[1,1999],\"m\":[1,4000],\"a\":[1,4000],\"s\":[1,4000]}\n]\n"
+		);
+		assert!(ins.export(&boxes, "xml").is_err());
+	}
+
+	#[test]
+	fn trace_formats() {
+		let (ins, objs) = Workflows::parse(EX_INPUT);
+		let line = "{x=787,m=2655,a=1222,s=2876}";
+		let trace = ins.trace(&objs[0]);
+		assert!(trace.accepted);
+		assert_eq!(
+			ins.format_trace(line, &trace, "txt").unwrap(),
+			format!("{line}: in (qqz) -> qqz (s>2770:qs) -> qs (lnx) -> lnx (m>1548:A) -> Accept")
+		);
+		assert_eq!(
+			ins.format_trace("{x=1679,m=44,a=2067,s=496}", &ins.trace(&objs[1]), "json")
+				.unwrap(),
+			concat!(
+				r#"{"part":"{x=1679,m=44,a=2067,s=496}","steps":["#,
+				r#"{"workflow":"in","rule":"s<1351:px"},{"workflow":"px","rule":"rfg"},"#,
+				r#"{"workflow":"rfg","rule":"s<537:gd"},{"workflow":"gd","rule":"R"}],"#,
+				r#""result":"Reject"}"#
+			)
+		);
+		assert!(ins.format_trace(line, &trace, "xml").is_err());
+	}
+
+	fn analyze(workflows: &str) -> Analysis {
+		Workflows::parse(workflows).0.analyze()
+	}
+
+	#[test]
+	fn unreachable_problems_are_not_fatal() {
+		assert!(part2("in{x<2000:A,R}\nzz{x<5:zz,R}").is_ok());
+		assert!(part2("in{x<2000:A,R}\nzz{x<5:nope,R}").is_ok());
+		assert!(part2("in{x<2000:A,x<1000:nope,R}").is_ok());
+	}
+
+	#[test]
+	fn reachable_problems_are_fatal() {
+		assert!(part2("in{x<2000:nope,R}").is_err());
+		assert!(part2("in{x<2000:in,R}").is_err());
+		assert!(part2("in{x<2000:a,R}\na{m>10:in,A}").is_err());
+		assert!(part2("nope{A}").is_err());
+	}
+
+	#[test]
+	fn cycles_follow_the_ratings() {
+		// nothing below 2000 is above 3000, so no part ever comes back to in
+		assert!(analyze("in{x<2000:a,R}\na{x>3000:in,A}").is_valid());
+		assert_eq!(
+			analyze("in{x<2000:a,R}\na{x<1000:in,A}").errors,
+			["cycle: in -> a -> in for ratings {x=1..999}"]
+		);
+	}
+
+	#[test]
+	fn unmatched_ratings() {
+		let (ins, _) = Workflows::parse("in{x<2000:A}\n\n{x=1,m=2}");
+		assert_eq!(
+			ins.analyze().errors,
+			["ratings {x=2000..4000,m=1..4000} fall through every rule of in"]
+		);
+		assert!(part2("in{x<2000:A}").is_err());
+
+		let analysis = analyze("in{A}\nzz{x<5:A}");
+		assert!(analysis.is_valid());
+		assert_eq!(
+			analysis.unmatched,
+			[("zz".to_string(), "{x=5..4000}".to_string())]
+		);
+	}
+
+	#[test]
+	fn undefined_is_not_unreachable() {
+		let analysis = analyze("in{A}\nzz{x<5:nope,R}");
+		assert_eq!(analysis.undefined, ["nope"]);
+		assert_eq!(analysis.unreachable, ["zz"]);
+	}
+
+	#[test]
+	fn always_true_tests() {
+		for (workflows, rule) in [("in{x>0:A,R}", "x>0:A"), ("in{x<4001:A,R}", "x<4001:A")] {
+			let analysis = analyze(workflows);
+			assert_eq!(
+				analysis.dead_rules,
+				[("in".to_string(), "R".to_string())],
+				"{workflows}"
+			);
+			let (ins, _) = Workflows::parse(workflows);
+			assert_eq!(ins.describe_rule(&ins.rules[0][0]), rule);
+		}
+	}
+
+	#[test]
+	fn always_false_tests() {
+		let analysis = analyze("in{x>4000:A,x<1:A,R}");
+		assert_eq!(analysis.dead_rules.len(), 2);
+		assert_eq!(part2("in{x>4000:A,x<1:A,A}").unwrap(), 4000);
+	}
+
+	#[test]
+	fn untested_attributes_count() {
+		assert_eq!(
+			part2("in{x<2000:A,R}\n\n{x=1,m=2,a=3,s=4}").unwrap(),
+			1999 * 4000 * 4000 * 4000
+		);
+	}
+
+	#[test]
+	fn attributes_come_from_the_ratings() {
+		assert_eq!(
+			part2("in{p<2000:q1,R}\nq1{q>5:A,R}\n\n{p=1,q=2}").unwrap(),
+			1999 * 3995
+		);
+		let (ins, _) = Workflows::parse("in{p<2000:A,R}\n\n{q=1,p=2,r=3}");
+		assert_eq!(
+			ins.export(&ins.accepted(), "csv").unwrap(),
+			"q_min,q_max,p_min,p_max,r_min,r_max\n1,4000,1,1999,1,4000\n"
+		);
+	}
+
+	#[test]
+	fn too_many_combinations() {
+		let input = |attr_cnt: usize| {
+			let ratings: Vec<_> = (0..attr_cnt).map(|i| format!("a{i}=1")).collect();
+			format!("in{{A}}\n\n{{{}}}", ratings.join(","))
+		};
+		assert_eq!(part2(&input(10)).unwrap(), 4000u128.pow(10));
+		assert!(part2(&input(11)).is_err());
+	}
+}
@@ -1,4 +1,10 @@
-use aoc_lib::{aoc, color_eyre::eyre::Result, to_lines};
+use std::{fmt::Write, path::PathBuf};
+
+use aoc_lib::{
+	aoc,
+	color_eyre::eyre::{bail, Result},
+	to_lines,
+};
 
 static INPUT: &str = include_str!("../../inputs/day22");
 
@@ -9,69 +15,62 @@ fn parse(s: &str) -> Vec3 {
 	(i.next().unwrap(), i.next().unwrap(), i.next().unwrap())
 }
 
-fn overlap(a: (i64, i64), b: (i64, i64)) -> bool {
-	// a starts in b but goes out
-	let a_in_b = a.0 >= b.0 && a.0 <= b.1;
-
-	// b starts in a but goes out
-	let b_in_a = b.0 >= a.0 && b.0 <= a.1;
-
-	a_in_b || b_in_a
-}
-
-fn collides(a: (Vec3, Vec3), b: (Vec3, Vec3)) -> bool {
-	let ax = (a.0 .0, a.1 .0);
-	let ay = (a.0 .1, a.1 .1);
-	let az = (a.0 .2, a.1 .2);
-	let bx = (b.0 .0, b.1 .0);
-	let by = (b.0 .1, b.1 .1);
-	let bz = (b.0 .2, b.1 .2);
-
-	overlap(ax, bx) && overlap(ay, by) && overlap(az, bz)
-}
-
-fn lower((from, to): (Vec3, Vec3)) -> (Vec3, Vec3) {
-	let from = (from.0, from.1, from.2 - 1);
-	let to = (to.0, to.1, to.2 - 1);
-	(from, to)
-}
+type Block = (Vec3, Vec3);
 
-fn get_blocks(input: &str) -> (Vec<Vec<usize>>, usize) {
+/// Let all the blocks fall, returning them in their settled positions
+/// along with which blocks each one rests on.
+/// Every block comes after all the blocks it rests on.
+fn get_blocks(input: &str) -> (Vec<Block>, Vec<Vec<usize>>) {
 	let mut blocks: Vec<_> = to_lines(input)
 		.map(|line| {
 			let (from, to) = line.split_once('~').unwrap();
-			(parse(from), parse(to))
+			let (from, to) = (parse(from), parse(to));
+			(
+				(from.0.min(to.0), from.1.min(to.1), from.2.min(to.2)),
+				(from.0.max(to.0), from.1.max(to.1), from.2.max(to.2)),
+			)
 		})
 		.collect();
 
 	blocks.sort_unstable_by_key(|(from, _)| from.2);
 
-	let mut supported_by = Vec::new();
+	let width = blocks.iter().map(|(_, to)| to.0).max().unwrap_or(0) + 1;
+	let depth = blocks.iter().map(|(_, to)| to.1).max().unwrap_or(0) + 1;
+	// for every (x, y) column: the height of its top and the block lying there
+	let mut tops = vec![(0, None); (width * depth) as usize];
 
-	for i in 0..blocks.len() {
-		loop {
-			let maybe = lower(blocks[i]);
-			let collisions: Vec<_> = blocks
-				.iter()
-				.enumerate()
-				.take(i)
-				.filter_map(|(i, &other)| collides(maybe, other).then_some(i))
-				.collect();
+	let mut supported_by = Vec::with_capacity(blocks.len());
 
-			if blocks[i].0 .2 > 1 && collisions.is_empty() {
-				blocks[i] = maybe;
-			} else {
-				supported_by.push(collisions);
-				break;
-			}
+	for (i, (from, to)) in blocks.iter_mut().enumerate() {
+		let footprint: Vec<_> = (from.1..=to.1)
+			.flat_map(|y| (from.0..=to.0).map(move |x| (y * width + x) as usize))
+			.collect();
+
+		let floor = footprint.iter().map(|&idx| tops[idx].0).max().unwrap();
+		let mut supports: Vec<_> = footprint
+			.iter()
+			.filter(|&&idx| tops[idx].0 == floor)
+			.filter_map(|&idx| tops[idx].1)
+			.collect();
+		supports.sort_unstable();
+		supports.dedup();
+		supported_by.push(supports);
+
+		let height = to.2 - from.2;
+		from.2 = floor + 1;
+		to.2 = from.2 + height;
+
+		for idx in footprint {
+			tops[idx] = (to.2, Some(i));
 		}
 	}
 
-	(supported_by, blocks.len())
+	(blocks, supported_by)
 }
 
 fn part1(input: &str) -> Result<usize> {
-	let (supported_by, block_cnt) = get_blocks(input);
+	let (blocks, supported_by) = get_blocks(input);
+	let block_cnt = blocks.len();
 
 	// for each block make sure that blocks that depend on it have more than one supporter
 	let res = (0..block_cnt)
@@ -87,28 +86,182 @@ fn part1(input: &str) -> Result<usize> {
 	Ok(res)
 }
 
-fn part2(input: &str) -> Result<usize> {
-	let (supported_by, block_cnt) = get_blocks(input);
+/// Dominator tree of the support graph, rooted at the ground.
+/// A block dominates another one if every chain of supports from the ground to the latter
+/// goes through the former, i.e. removing it makes the other one fall.
+struct Dominators {
+	/// immediate dominator of every block, `None` being the ground
+	idom: Vec<Option<usize>>,
+	children: Vec<Vec<usize>>,
+	/// size of every block's subtree, including the block itself
+	sizes: Vec<usize>,
+}
 
-	// for each block count how many would fall if it was removed,
-	// and then how many would fall if those were also removed,
-	// and then how many would fall if those were also removed,
-	// ...
-	let res = (0..block_cnt)
-		.map(|i| {
-			supported_by
+impl Dominators {
+	/// Blocks have to be sorted such that they're only supported by blocks before them,
+	/// which `get_blocks` guarantees.
+	fn new(supported_by: &[Vec<usize>]) -> Self {
+		let mut idom: Vec<Option<usize>> = Vec::with_capacity(supported_by.len());
+		let mut depth = Vec::with_capacity(supported_by.len());
+		let depth_of = |depth: &[usize], node: Option<usize>| node.map(|i| depth[i]).unwrap_or(0);
+
+		for sup in supported_by {
+			// the immediate dominator is the closest common ancestor of all supporters
+			let dom = sup
 				.iter()
-				.enumerate()
-				.skip(i + 1)
-				.fold(vec![i], |mut fallen, (j, sup)| {
-					if !sup.is_empty() && sup.iter().all(|s| fallen.contains(s)) {
-						fallen.push(j);
+				.map(|&s| Some(s))
+				.reduce(|mut a, mut b| {
+					while a != b {
+						if depth_of(&depth, a) > depth_of(&depth, b) {
+							a = idom[a.unwrap()];
+						} else {
+							b = idom[b.unwrap()];
+						}
 					}
-					fallen
+					a
 				})
-				.len() - 1
-		})
-		.sum();
+				.flatten();
+			depth.push(depth_of(&depth, dom) + 1);
+			idom.push(dom);
+		}
+
+		let mut children = vec![Vec::new(); idom.len()];
+		let mut sizes = vec![1; idom.len()];
+		for i in (0..idom.len()).rev() {
+			if let Some(dom) = idom[i] {
+				children[dom].push(i);
+				sizes[dom] += sizes[i];
+			}
+		}
+
+		Dominators {
+			idom,
+			children,
+			sizes,
+		}
+	}
+
+	fn fall_count(&self, block: usize) -> usize {
+		self.sizes[block] - 1
+	}
+
+	/// All the blocks which fall if `block` is removed.
+	fn falling(&self, block: usize) -> Vec<usize> {
+		let mut res = Vec::with_capacity(self.fall_count(block));
+		let mut stack = self.children[block].clone();
+		while let Some(curr) = stack.pop() {
+			res.push(curr);
+			stack.extend_from_slice(&self.children[curr]);
+		}
+		res.sort_unstable();
+		res
+	}
+}
+
+/// Corners of a block, the n-th one being on the far side of the x, y and z axes
+/// if the first, second and third bit of n are set, respectively.
+fn corners((from, to): Block) -> [Vec3; 8] {
+	std::array::from_fn(|i| {
+		let x = if i & 1 == 0 { from.0 } else { to.0 + 1 };
+		let y = if i & 2 == 0 { from.1 } else { to.1 + 1 };
+		let z = if i & 4 == 0 { from.2 } else { to.2 + 1 };
+		(x, y, z)
+	})
+}
+
+/// Outward normal of every face of a block along with its corners, counter-clockwise.
+const FACES: [(Vec3, [usize; 4]); 6] = [
+	((0, 0, -1), [0, 2, 3, 1]),
+	((0, 0, 1), [4, 5, 7, 6]),
+	((0, -1, 0), [0, 1, 5, 4]),
+	((0, 1, 0), [2, 6, 7, 3]),
+	((-1, 0, 0), [0, 4, 6, 2]),
+	((1, 0, 0), [1, 3, 7, 5]),
+];
+
+fn export_mesh(blocks: &[Block], dominators: &Dominators, format: &str) -> Result<String> {
+	let max_fall = (0..blocks.len())
+		.map(|i| dominators.fall_count(i))
+		.max()
+		.unwrap_or(0);
+	let group = |i| match dominators.fall_count(i) {
+		0 => "safe",
+		cnt if cnt == max_fall => "largest_chain",
+		_ => "brick",
+	};
+
+	let mut res = String::new();
+	match format {
+		"obj" => {
+			for (i, &block) in blocks.iter().enumerate() {
+				writeln!(res, "g brick_{i} {}", group(i))?;
+				for (x, y, z) in corners(block) {
+					writeln!(res, "v {x} {y} {z}")?;
+				}
+				for (_, face) in FACES {
+					// obj indices are 1-based and count from the start of the file
+					let [a, b, c, d] = face.map(|corner| i * 8 + corner + 1);
+					writeln!(res, "f {a} {b} {c} {d}")?;
+				}
+			}
+		}
+		"stl" => {
+			// most readers only take the first solid, so every brick goes into the same one
+			writeln!(res, "solid bricks")?;
+			for &block in blocks {
+				let corners = corners(block);
+				for ((nx, ny, nz), [a, b, c, d]) in FACES {
+					for triangle in [[a, b, c], [a, c, d]] {
+						writeln!(res, "facet normal {nx} {ny} {nz}")?;
+						writeln!(res, "outer loop")?;
+						for (x, y, z) in triangle.map(|corner| corners[corner]) {
+							writeln!(res, "vertex {x} {y} {z}")?;
+						}
+						writeln!(res, "endloop")?;
+						writeln!(res, "endfacet")?;
+					}
+				}
+			}
+			writeln!(res, "endsolid bricks")?;
+		}
+		format => bail!("unknown mesh format: {format}"),
+	}
+
+	Ok(res)
+}
+
+fn part2(input: &str) -> Result<usize> {
+	let (blocks, supported_by) = get_blocks(input);
+	let dominators = Dominators::new(&supported_by);
+
+	if std::env::var_os("DAY22_CHAIN").is_some() {
+		for (i, ((from, to), dom)) in blocks.iter().zip(&dominators.idom).enumerate() {
+			let falling: Vec<_> = dominators
+				.falling(i)
+				.into_iter()
+				.map(|j| j.to_string())
+				.collect();
+			let dom = dom.map(|d| d.to_string()).unwrap_or("ground".to_string());
+			println!(
+				"{i} ({},{},{}~{},{},{}) dominated by {dom}, {} fall: [{}]",
+				from.0,
+				from.1,
+				from.2,
+				to.0,
+				to.1,
+				to.2,
+				falling.len(),
+				falling.join(", ")
+			);
+		}
+	}
+
+	if let Some(path) = std::env::var_os("DAY22_MESH").map(PathBuf::from) {
+		let format = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+		std::fs::write(&path, export_mesh(&blocks, &dominators, format)?)?;
+	}
+
+	let res = (0..blocks.len()).map(|i| dominators.fall_count(i)).sum();
 
 	Ok(res)
 }
@@ -129,3 +282,65 @@ aoc! {
 	part1 => (EX_INPUT) 5,
 	part2 => (EX_INPUT) 7
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Remove the block and let everything else settle, one block at a time.
+	fn brute_force_falling(supported_by: &[Vec<usize>], block: usize) -> Vec<usize> {
+		let mut fallen = vec![false; supported_by.len()];
+		fallen[block] = true;
+		for (i, sup) in supported_by.iter().enumerate().skip(block + 1) {
+			fallen[i] = !sup.is_empty() && sup.iter().all(|&s| fallen[s]);
+		}
+		(0..supported_by.len())
+			.filter(|&i| i != block && fallen[i])
+			.collect()
+	}
+
+	#[test]
+	fn falling_agrees_with_removal() {
+		let (_, supported_by) = get_blocks(EX_INPUT);
+		let dominators = Dominators::new(&supported_by);
+		for block in 0..supported_by.len() {
+			let falling = dominators.falling(block);
+			assert_eq!(
+				falling,
+				brute_force_falling(&supported_by, block),
+				"{block}"
+			);
+			assert_eq!(falling.len(), dominators.fall_count(block));
+		}
+	}
+
+	#[test]
+	fn mesh_formats() {
+		let (blocks, supported_by) = get_blocks(EX_INPUT);
+		let dominators = Dominators::new(&supported_by);
+		let count =
+			|mesh: &str, prefix: &str| mesh.lines().filter(|line| line.starts_with(prefix)).count();
+
+		let obj = export_mesh(&blocks, &dominators, "obj").unwrap();
+		assert_eq!(count(&obj, "v "), 7 * 8);
+		assert_eq!(count(&obj, "f "), 7 * 6);
+		assert_eq!(count(&obj, "g "), 7);
+		assert_eq!(count(&obj, "usemtl"), 0);
+		// A brings down everything above it, F only G
+		for (group, cnt) in [("safe", 5), ("largest_chain", 1), ("brick", 1)] {
+			let groups = obj
+				.lines()
+				.filter(|line| line.starts_with("g ") && line.ends_with(&format!(" {group}")));
+			assert_eq!(groups.count(), cnt, "{group}");
+		}
+		assert!(obj.contains("g brick_0 largest_chain\n"));
+
+		let stl = export_mesh(&blocks, &dominators, "stl").unwrap();
+		assert_eq!(count(&stl, "solid "), 1);
+		assert_eq!(count(&stl, "endsolid "), 1);
+		assert_eq!(count(&stl, "facet normal"), 7 * 6 * 2);
+		assert_eq!(count(&stl, "vertex"), 7 * 6 * 2 * 3);
+
+		assert!(export_mesh(&blocks, &dominators, "ply").is_err());
+	}
+}